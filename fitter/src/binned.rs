@@ -0,0 +1,366 @@
+use crate::{
+    dataset::Dataset,
+    likelihood::{Likelihood, Objective, Order},
+    linalg::Matrix,
+    quadrature::{gauss_legendre, product_rule},
+    summation::{Accumulator, Summation},
+};
+
+// counts in boxes of data space
+pub struct Histogram {
+    dimension: usize,
+    // `dimension` (lower, upper) pairs per bin
+    edges: Vec<(f64, f64)>,
+    counts: Vec<f64>,
+}
+
+// how the model density is integrated over each bin
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Integration {
+    Midpoint,
+    Simpson,
+    GaussLegendre(usize),
+}
+
+// what is minimized for a histogram; the chi-squared statistics are halved so that, like the nll, they rise by 0.5 at one sigma
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Statistic {
+    Poisson,
+    // bins without entries are skipped
+    Neyman,
+    Pearson,
+}
+
+impl Histogram {
+    pub fn new(dimension: usize) -> Self {
+        Self { dimension, edges: Vec::new(), counts: Vec::new() }
+    }
+
+    // the grid of bins between consecutive `edges` of each data field, filled with the (weighted) events of a dataset;
+    // events outside the grid are dropped
+    pub fn from_dataset(dataset: &Dataset, edges: &[Vec<f64>]) -> Self {
+        if edges.len() != dataset.dimension() {
+            panic!("histogram needs edges for `{}` data fields, but was given `{}`", dataset.dimension(), edges.len());
+        }
+        if edges.iter().any(|edges| edges.len() < 2 || edges.windows(2).any(|pair| pair[0] >= pair[1])) {
+            panic!("histogram edges must be increasing, with at least two per data field");
+        }
+        let shape: Vec<usize> = edges.iter().map(|edges| edges.len() - 1).collect();
+        let mut counts = vec![0.0; shape.iter().product()];
+        for (i, event) in dataset.events().enumerate() {
+            let mut index = 0;
+            for (d, (value, edges)) in event.iter().zip(edges).enumerate().rev() {
+                let bin = edges.partition_point(|edge| edge <= value);
+                if bin == 0 || bin == edges.len() && *value > edges[edges.len() - 1] {
+                    index = usize::MAX;
+                    break;
+                }
+                index = index * shape[d] + (bin - 1).min(shape[d] - 1);
+            }
+            if index != usize::MAX {
+                counts[index] += dataset.weights().map_or(1.0, |weights| weights[i]);
+            }
+        }
+
+        let mut histogram = Self::new(dataset.dimension());
+        for (index, count) in counts.into_iter().enumerate() {
+            let mut rest = index;
+            let bin: Vec<(f64, f64)> = edges
+                .iter()
+                .zip(&shape)
+                .map(|(edges, size)| {
+                    let i = rest % size;
+                    rest /= size;
+                    (edges[i], edges[i + 1])
+                })
+                .collect();
+            histogram.push(&bin, count);
+        }
+        histogram
+    }
+
+    pub fn push(&mut self, bin: &[(f64, f64)], count: f64) {
+        if bin.len() != self.dimension {
+            panic!("bin has `{}` ranges, but the histogram has dimension `{}`", bin.len(), self.dimension);
+        }
+        if bin.iter().any(|(lower, upper)| !lower.is_finite() || !upper.is_finite() || lower >= upper) {
+            panic!("bin ranges must be finite with `lower < upper`");
+        }
+        if !count.is_finite() || count < 0.0 {
+            panic!("bin count must be finite and non-negative, but was `{}`", count);
+        }
+        self.edges.extend_from_slice(bin);
+        self.counts.push(count);
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    pub fn total(&self) -> f64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn bins(&self) -> impl Iterator<Item = (&[(f64, f64)], f64)> {
+        self.edges.chunks_exact(self.dimension.max(1)).zip(self.counts.iter().copied())
+    }
+}
+
+impl Integration {
+    // nodes and weights on [-1, 1]
+    fn rule(&self) -> (Vec<f64>, Vec<f64>) {
+        match self {
+            Self::Midpoint => (vec![0.0], vec![2.0]),
+            Self::Simpson => (vec![-1.0, 0.0, 1.0], vec![1.0 / 3.0, 4.0 / 3.0, 1.0 / 3.0]),
+            Self::GaussLegendre(points) => {
+                if *points == 0 {
+                    panic!("gauss-legendre integration needs at least one point");
+                }
+                gauss_legendre(*points)
+            }
+        }
+    }
+}
+
+impl Statistic {
+    // the contribution of a bin with count `n` and expectation `mu`, with its first and second derivative in `mu`
+    fn terms(&self, n: f64, mu: f64) -> (f64, f64, f64) {
+        match self {
+            // measured from the saturated model, `mu - n + n ln(n / mu)`
+            Self::Poisson => {
+                let log = if n > 0.0 {
+                    n * (n / mu).ln()
+                } else {
+                    0.0
+                };
+                (mu - n + log, 1.0 - n / mu, n / (mu * mu))
+            }
+            Self::Neyman if n > 0.0 => (0.5 * (n - mu) * (n - mu) / n, (mu - n) / n, 1.0 / n),
+            Self::Neyman => (0.0, 0.0, 0.0),
+            Self::Pearson => (0.5 * (n - mu) * (n - mu) / mu, 0.5 * (1.0 - n * n / (mu * mu)), n * n / (mu * mu * mu)),
+        }
+    }
+}
+
+// a binned fit statistic, comparing each bin's count with the model density integrated over the bin; the density is
+// `exp(-nll)` of the per-event likelihood, so models must be normalized, and are scaled to the histogram total unless extended
+pub struct Binned<'a, L: Likelihood> {
+    pub likelihood: &'a L,
+    pub histogram: &'a Histogram,
+    pub statistic: Statistic,
+    pub summation: Summation,
+    // quadrature points of every bin, with their weights
+    points: Vec<Vec<(Vec<f64>, f64)>>,
+}
+
+impl<'a, L: Likelihood> Binned<'a, L> {
+    pub fn new(likelihood: &'a L, histogram: &'a Histogram, integration: Integration, statistic: Statistic) -> Self {
+        if likelihood.num_data() != histogram.dimension() {
+            panic!("model takes `{}` data values, but the histogram has dimension `{}`", likelihood.num_data(), histogram.dimension());
+        }
+        let (nodes, weights) = integration.rule();
+        let points = histogram.bins().map(|(bin, _)| product_rule(bin, &nodes, &weights)).collect();
+        Self { likelihood, histogram, statistic, summation: Summation::Kahan, points }
+    }
+
+    // the expected count of a bin, with its gradient and packed hessian as far as `order` asks
+    fn expected(&self, prepared: &[f64], points: &[(Vec<f64>, f64)], order: Order, gradient: &mut [f64], hessian: &mut [f64]) -> f64 {
+        let n = gradient.len();
+        let scale = if self.likelihood.is_extended() {
+            1.0
+        } else {
+            self.histogram.total()
+        };
+        let mut event_gradient = vec![0.0; n];
+        let mut event_hessian = vec![0.0; hessian.len()];
+        let mut expected = 0.0;
+        gradient.fill(0.0);
+        hessian.fill(0.0);
+        for (point, weight) in points {
+            let nll = match order {
+                Order::Value => self.likelihood.value(prepared, point),
+                Order::Gradient => self.likelihood.gradient(prepared, point, &mut event_gradient),
+                Order::Hessian => self.likelihood.hessian(prepared, point, &mut event_gradient, &mut event_hessian),
+            };
+            // with `f = exp(-nll)`, `df = -f dnll` and `d2f = f (dnll dnll^T - d2nll)`
+            let density = scale * weight * (-nll).exp();
+            expected += density;
+            gradient.iter_mut().zip(&event_gradient).for_each(|(g, e)| *g -= density * e);
+            let mut k = 0;
+            for i in 0..n {
+                for j in i..n {
+                    hessian[k] += density * (event_gradient[i] * event_gradient[j] - event_hessian[k]);
+                    k += 1;
+                }
+            }
+        }
+        expected
+    }
+
+    // sums the statistic over bins, with the gradient and packed hessian as far as `order` asks
+    fn evaluate(&self, x: &[f64], order: Order) -> Vec<f64> {
+        let n = x.len();
+        let size = n * (n + 1) / 2;
+        let prepared = self.likelihood.prepare(x, order);
+        let mut gradient = vec![0.0; n];
+        let mut hessian = vec![0.0; size];
+        let mut terms = vec![0.0; 1 + n + size];
+        let mut accumulator = Accumulator::new(self.summation, terms.len());
+        for ((_, count), points) in self.histogram.bins().zip(&self.points) {
+            let expected = self.expected(&prepared, points, order, &mut gradient, &mut hessian);
+            let (value, first, second) = self.statistic.terms(count, expected);
+            terms[0] = value;
+            let mut k = 0;
+            for i in 0..n {
+                terms[1 + i] = first * gradient[i];
+                for j in i..n {
+                    terms[1 + n + k] = second * gradient[i] * gradient[j] + first * hessian[k];
+                    k += 1;
+                }
+            }
+            accumulator.add(&terms);
+        }
+        accumulator.total()
+    }
+}
+
+impl<L: Likelihood> Objective for Binned<'_, L> {
+    fn dimension(&self) -> usize {
+        self.likelihood.num_parameters()
+    }
+
+    fn value(&self, x: &[f64]) -> f64 {
+        self.evaluate(x, Order::Value)[0]
+    }
+
+    fn gradient(&self, x: &[f64], gradient: &mut [f64]) -> f64 {
+        let totals = self.evaluate(x, Order::Gradient);
+        gradient.copy_from_slice(&totals[1..=x.len()]);
+        totals[0]
+    }
+
+    fn hessian(&self, x: &[f64], gradient: &mut [f64], hessian: &mut Matrix) -> f64 {
+        let n = x.len();
+        let totals = self.evaluate(x, Order::Hessian);
+        gradient.copy_from_slice(&totals[1..=n]);
+        *hessian = Matrix::from_packed(n, &totals[1 + n..]);
+        totals[0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{likelihood::Model, minimize::Fitter, parameter::Parameter};
+
+    // the density `1 + a (x - 1/2)` on [0, 1], which every rule integrates exactly
+    fn linear() -> Model<1, 1, 1> {
+        Model {
+            names: ["a"],
+            likelihood: |p, d| -(1.0 + p[0] * (d[0] - 0.5)).ln(),
+            gradient: |p, d| {
+                let density = 1.0 + p[0] * (d[0] - 0.5);
+                (-density.ln(), [-(d[0] - 0.5) / density])
+            },
+            hessian: |p, d| {
+                let (offset, density) = (d[0] - 0.5, 1.0 + p[0] * (d[0] - 0.5));
+                (-density.ln(), [-offset / density], [offset * offset / (density * density)])
+            },
+            extended: None,
+        }
+    }
+
+    // the same density scaled by a yield `nu`
+    fn extended_linear() -> Model<2, 1, 3> {
+        Model {
+            names: ["a", "nu"],
+            likelihood: |p, d| -(p[1] * (1.0 + p[0] * (d[0] - 0.5))).ln(),
+            gradient: |p, d| {
+                let density = 1.0 + p[0] * (d[0] - 0.5);
+                (-(p[1] * density).ln(), [-(d[0] - 0.5) / density, -1.0 / p[1]])
+            },
+            hessian: |p, d| {
+                let (offset, density) = (d[0] - 0.5, 1.0 + p[0] * (d[0] - 0.5));
+                (-(p[1] * density).ln(), [-offset / density, -1.0 / p[1]], [offset * offset / (density * density), 0.0, 1.0 / (p[1] * p[1])])
+            },
+            extended: Some(|_| (0.0, [0.0; 2], [0.0; 3])),
+        }
+    }
+
+    // ten bins holding exactly the expected counts of `total` events at `a = 0.4`
+    fn expected_histogram(total: f64) -> Histogram {
+        let mut histogram = Histogram::new(1);
+        for i in 0..10 {
+            let (lower, upper) = (i as f64 / 10.0, (i + 1) as f64 / 10.0);
+            histogram.push(&[(lower, upper)], total * (upper - lower) * (1.0 + 0.4 * (0.5 * (lower + upper) - 0.5)));
+        }
+        histogram
+    }
+
+    #[test]
+    fn exact_counts_are_fitted_exactly() {
+        let histogram = expected_histogram(1000.0);
+        let fitter = Fitter { tolerance: 1e-14, ..Fitter::default() };
+        for integration in [Integration::Midpoint, Integration::Simpson, Integration::GaussLegendre(3)] {
+            for statistic in [Statistic::Poisson, Statistic::Neyman, Statistic::Pearson] {
+                let result = fitter.fit_binned(&linear(), &histogram, &[Parameter::from(0.0)], integration, statistic);
+                assert!(result.converged, "{:?} {:?}", integration, statistic);
+                assert!((result.parameters[0] - 0.4).abs() <= 1e-6, "{:?} {:?}: {}", integration, statistic, result.parameters[0]);
+                assert!(result.nll.abs() <= 1e-9, "{:?} {:?}: {}", integration, statistic, result.nll);
+                assert!(result.error("a").unwrap() > 0.0);
+            }
+        }
+
+        let parameters = [Parameter::from(0.0), Parameter::from(800.0)];
+        let result = fitter.fit_binned(&extended_linear(), &histogram, &parameters, Integration::Midpoint, Statistic::Poisson);
+        assert!((result.parameters[0] - 0.4).abs() <= 1e-6 && (result.parameters[1] - 1000.0).abs() <= 1e-6, "{:?}", result.parameters);
+        // a poisson total has variance equal to itself
+        assert!((result.error("nu").unwrap() - 1000f64.sqrt()).abs() <= 1e-6);
+    }
+
+    #[test]
+    fn derivatives_match_differences() {
+        let histogram = expected_histogram(500.0);
+        for statistic in [Statistic::Poisson, Statistic::Neyman, Statistic::Pearson] {
+            let model = extended_linear();
+            let binned = Binned::new(&model, &histogram, Integration::Simpson, statistic);
+            let x = [0.1, 650.0];
+            let (mut gradient, mut hessian) = (vec![0.0; 2], Matrix::zeros(2));
+            binned.hessian(&x, &mut gradient, &mut hessian);
+            for i in 0..2 {
+                let step = 1e-6 * x[i].abs().max(1.0);
+                let (mut up, mut down) = (x, x);
+                up[i] += step;
+                down[i] -= step;
+                let difference = (binned.value(&up) - binned.value(&down)) / (2.0 * step);
+                assert!((gradient[i] - difference).abs() <= 1e-5 * difference.abs().max(1.0), "{:?} gradient {}", statistic, i);
+                let (mut up_gradient, mut down_gradient) = (vec![0.0; 2], vec![0.0; 2]);
+                binned.gradient(&up, &mut up_gradient);
+                binned.gradient(&down, &mut down_gradient);
+                for j in 0..2 {
+                    let difference = (up_gradient[j] - down_gradient[j]) / (2.0 * step);
+                    assert!((hessian[(i, j)] - difference).abs() <= 1e-5 * difference.abs().max(1.0), "{:?} hessian {} {}", statistic, i, j);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn datasets_fill_the_bins_they_fall_in() {
+        let dataset = Dataset::from_weighted_events(&[[0.1, 5.0], [0.6, 5.0], [0.6, 7.5], [1.0, 9.0], [1.2, 6.0], [-0.1, 6.0]], &[1.0, 2.0, 0.5, 3.0, 4.0, 5.0]);
+        let histogram = Histogram::from_dataset(&dataset, &[vec![0.0, 0.5, 1.0], vec![5.0, 7.0, 9.0]]);
+        assert_eq!(histogram.len(), 4);
+        // the last edge of each field is inside, and events beyond the edges are dropped
+        let bins: Vec<(Vec<(f64, f64)>, f64)> = histogram.bins().map(|(bin, count)| (bin.to_vec(), count)).collect();
+        assert_eq!(bins, [(vec![(0.0, 0.5), (5.0, 7.0)], 1.0), (vec![(0.5, 1.0), (5.0, 7.0)], 2.0), (vec![(0.0, 0.5), (7.0, 9.0)], 0.0), (vec![(0.5, 1.0), (7.0, 9.0)], 3.5)]);
+        assert_eq!(histogram.total(), 6.5);
+    }
+}
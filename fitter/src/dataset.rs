@@ -0,0 +1,84 @@
+pub struct Dataset {
+    dimension: usize,
+    values: Vec<f64>,
+    // per-event weights, absent for unweighted datasets
+    weights: Option<Vec<f64>>,
+}
+
+impl Dataset {
+    pub fn new(dimension: usize) -> Self {
+        Self { dimension, values: Vec::new(), weights: None }
+    }
+
+    pub fn from_events<const D: usize>(events: &[[f64; D]]) -> Self {
+        Self { dimension: D, values: events.iter().flatten().copied().collect(), weights: None }
+    }
+
+    pub fn from_weighted_events<const D: usize>(events: &[[f64; D]], weights: &[f64]) -> Self {
+        if events.len() != weights.len() {
+            panic!("dataset has `{}` events, but was given `{}` weights", events.len(), weights.len());
+        }
+        let mut dataset = Self::new(D);
+        events.iter().zip(weights).for_each(|(event, weight)| dataset.push_weighted(event, *weight));
+        dataset
+    }
+
+    // events pushed into a weighted dataset get weight one
+    pub fn push(&mut self, event: &[f64]) {
+        if event.len() != self.dimension {
+            panic!("event has `{}` values, but the dataset has dimension `{}`", event.len(), self.dimension);
+        }
+        self.values.extend_from_slice(event);
+        if let Some(weights) = &mut self.weights {
+            weights.push(1.0);
+        }
+    }
+
+    // the first weighted event gives every earlier event weight one
+    pub fn push_weighted(&mut self, event: &[f64], weight: f64) {
+        if !weight.is_finite() {
+            panic!("event weight must be finite, but was `{}`", weight);
+        }
+        let len = self.len();
+        self.weights.get_or_insert_with(|| vec![1.0; len]);
+        self.push(event);
+        if let Some(last) = self.weights.as_mut().and_then(|weights| weights.last_mut()) {
+            *last = weight;
+        }
+    }
+
+    pub fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len().checked_div(self.dimension).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn events(&self) -> impl Iterator<Item = &[f64]> {
+        self.values.chunks_exact(self.dimension.max(1))
+    }
+
+    pub fn is_weighted(&self) -> bool {
+        self.weights.is_some()
+    }
+
+    pub fn weights(&self) -> Option<&[f64]> {
+        self.weights.as_deref()
+    }
+
+    // the raw values in blocks of up to `events` events each, with their weights
+    pub(crate) fn blocks(&self, events: usize) -> impl Iterator<Item = (&[f64], Option<&[f64]>)> {
+        let mut weights = self.weights.as_deref().map(|weights| weights.chunks(events));
+        self.values.chunks(events * self.dimension.max(1)).map(move |values| (values, weights.as_mut().and_then(|weights| weights.next())))
+    }
+
+    // one vector per data field, for the generated `_batch` functions
+    pub fn columns(&self) -> Vec<Vec<f64>> {
+        (0..self.dimension).map(|d| self.events().map(|event| event[d]).collect()).collect()
+    }
+}
@@ -0,0 +1,25 @@
+mod binned;
+mod dataset;
+mod likelihood;
+mod linalg;
+mod minimize;
+mod parameter;
+mod profile;
+mod quadrature;
+mod result;
+mod subspace;
+mod summation;
+#[cfg(test)]
+mod toys;
+mod transform;
+
+pub use binned::{Binned, Histogram, Integration, Statistic};
+pub use dataset::Dataset;
+pub use likelihood::{ExtendedFn, GradientFn, HessianFn, Likelihood, LikelihoodFn, Model, Nll, Objective, Order};
+pub use linalg::Matrix;
+pub use minimize::{Fitter, Method, Minimum};
+pub use parameter::{Bounds, FloatingParameter, Parameter};
+pub use profile::Interval;
+pub use quadrature::{gauss_legendre, grid, log_integral, log_normalization};
+pub use result::FitResult;
+pub use summation::Summation;
@@ -0,0 +1,339 @@
+use std::thread;
+
+use crate::{
+    dataset::Dataset,
+    linalg::Matrix,
+    summation::{Accumulator, Summation},
+};
+
+// events summed together before partial sums are combined; fixed so that the result does not depend on the thread count
+const BLOCK_SIZE: usize = 4096;
+
+// derivatives requested from a likelihood evaluation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    Value,
+    Gradient,
+    Hessian,
+}
+
+// per-event negative log-likelihood, as emitted by `define_model`; the per-event methods take the output of `prepare`
+pub trait Likelihood: Sync {
+    fn num_parameters(&self) -> usize;
+    fn num_data(&self) -> usize;
+    fn parameter_names(&self) -> Vec<String>;
+
+    // parameter-only values computed once per evaluation and shared by every event, by default the parameters themselves
+    fn prepare(&self, parameters: &[f64], _order: Order) -> Vec<f64> {
+        parameters.to_vec()
+    }
+
+    fn value(&self, prepared: &[f64], data: &[f64]) -> f64;
+    fn gradient(&self, prepared: &[f64], data: &[f64], gradient: &mut [f64]) -> f64;
+    fn hessian(&self, prepared: &[f64], data: &[f64], gradient: &mut [f64], hessian: &mut [f64]) -> f64;
+
+    // parameter-only term added once per dataset rather than per event, such as the total yield of an extended likelihood
+    fn extended(&self, _parameters: &[f64], gradient: &mut [f64], hessian: &mut [f64]) -> f64 {
+        gradient.fill(0.0);
+        hessian.fill(0.0);
+        0.0
+    }
+
+    // whether `exp(-value)` is the expected event density rather than a normalized one
+    fn is_extended(&self) -> bool {
+        false
+    }
+}
+
+pub type LikelihoodFn<const P: usize, const D: usize> = fn([f64; P], [f64; D]) -> f64;
+pub type GradientFn<const P: usize, const D: usize> = fn([f64; P], [f64; D]) -> (f64, [f64; P]);
+pub type HessianFn<const P: usize, const D: usize, const H: usize> = fn([f64; P], [f64; D]) -> (f64, [f64; P], [f64; H]);
+pub type ExtendedFn<const P: usize, const H: usize> = fn([f64; P]) -> (f64, [f64; P], [f64; H]);
+
+// wraps the `_likelihood`, `_grad` and `_hess` functions of a generated model, and `_extended` for extended models
+pub struct Model<const P: usize, const D: usize, const H: usize> {
+    pub names: [&'static str; P],
+    pub likelihood: LikelihoodFn<P, D>,
+    pub gradient: GradientFn<P, D>,
+    pub hessian: HessianFn<P, D, H>,
+    pub extended: Option<ExtendedFn<P, H>>,
+}
+
+impl<const P: usize, const D: usize, const H: usize> Model<P, D, H> {
+    fn arrays(parameters: &[f64], data: &[f64]) -> ([f64; P], [f64; D]) {
+        let parameters = parameters.try_into().unwrap_or_else(|_| panic!("model takes `{}` parameters, but was given `{}`", P, parameters.len()));
+        let data = data.try_into().unwrap_or_else(|_| panic!("model takes `{}` data values, but was given `{}`", D, data.len()));
+        (parameters, data)
+    }
+}
+
+impl<const P: usize, const D: usize, const H: usize> Likelihood for Model<P, D, H> {
+    fn num_parameters(&self) -> usize {
+        P
+    }
+
+    fn num_data(&self) -> usize {
+        D
+    }
+
+    fn parameter_names(&self) -> Vec<String> {
+        self.names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn value(&self, parameters: &[f64], data: &[f64]) -> f64 {
+        let (parameters, data) = Self::arrays(parameters, data);
+        (self.likelihood)(parameters, data)
+    }
+
+    fn gradient(&self, parameters: &[f64], data: &[f64], gradient: &mut [f64]) -> f64 {
+        let (parameters, data) = Self::arrays(parameters, data);
+        let (value, g) = (self.gradient)(parameters, data);
+        gradient.copy_from_slice(&g);
+        value
+    }
+
+    fn hessian(&self, parameters: &[f64], data: &[f64], gradient: &mut [f64], hessian: &mut [f64]) -> f64 {
+        let (parameters, data) = Self::arrays(parameters, data);
+        let (value, g, h) = (self.hessian)(parameters, data);
+        gradient.copy_from_slice(&g);
+        hessian.copy_from_slice(&h);
+        value
+    }
+
+    fn extended(&self, parameters: &[f64], gradient: &mut [f64], hessian: &mut [f64]) -> f64 {
+        let Some(extended) = self.extended else {
+            gradient.fill(0.0);
+            hessian.fill(0.0);
+            return 0.0;
+        };
+        let (parameters, _) = Self::arrays(parameters, &[0.0; D]);
+        let (value, g, h) = extended(parameters);
+        gradient.copy_from_slice(&g);
+        hessian.copy_from_slice(&h);
+        value
+    }
+
+    fn is_extended(&self) -> bool {
+        self.extended.is_some()
+    }
+}
+
+// a smooth function of a parameter vector to be minimized
+pub trait Objective {
+    fn dimension(&self) -> usize;
+    fn value(&self, x: &[f64]) -> f64;
+    fn gradient(&self, x: &[f64], gradient: &mut [f64]) -> f64;
+    fn hessian(&self, x: &[f64], gradient: &mut [f64], hessian: &mut Matrix) -> f64;
+}
+
+// negative log-likelihood of a whole dataset, summed over `threads` threads, with each event scaled by its weight
+pub struct Nll<'a, L: Likelihood> {
+    pub likelihood: &'a L,
+    pub dataset: &'a Dataset,
+    pub threads: usize,
+    pub summation: Summation,
+}
+
+impl<'a, L: Likelihood> Nll<'a, L> {
+    pub fn new(likelihood: &'a L, dataset: &'a Dataset) -> Self {
+        if likelihood.num_data() != dataset.dimension() {
+            panic!("model takes `{}` data values, but the dataset has dimension `{}`", likelihood.num_data(), dataset.dimension());
+        }
+        Self { likelihood, dataset, threads: 1, summation: Summation::Kahan }
+    }
+
+    // sums `size` totals over the dataset, with `event` writing the terms of one event given its weight, which are then
+    // scaled by that weight; each thread takes a contiguous run of blocks and the per-block totals are combined in order
+    fn reduce(&self, size: usize, event: impl Fn(&[f64], f64, &mut [f64]) + Sync) -> Vec<f64> {
+        let dimension = self.dataset.dimension().max(1);
+        let blocks: Vec<(&[f64], Option<&[f64]>)> = self.dataset.blocks(BLOCK_SIZE).collect();
+        let sum_block = |(values, weights): &(&[f64], Option<&[f64]>)| {
+            let mut accumulator = Accumulator::new(self.summation, size);
+            let mut terms = vec![0.0; size];
+            for (i, values) in values.chunks_exact(dimension).enumerate() {
+                let weight = weights.map(|weights| weights[i]);
+                event(values, weight.unwrap_or(1.0), &mut terms);
+                if let Some(weight) = weight {
+                    terms.iter_mut().for_each(|t| *t *= weight);
+                }
+                accumulator.add(&terms);
+            }
+            accumulator.total()
+        };
+        let mut partials = vec![Vec::new(); blocks.len()];
+        let per_thread = blocks.len().div_ceil(self.threads.max(1)).max(1);
+        if per_thread >= blocks.len() {
+            blocks.iter().zip(&mut partials).for_each(|(block, partial)| *partial = sum_block(block));
+        } else {
+            thread::scope(|scope| {
+                for (blocks, partials) in blocks.chunks(per_thread).zip(partials.chunks_mut(per_thread)) {
+                    let sum_block = &sum_block;
+                    scope.spawn(move || blocks.iter().zip(partials).for_each(|(block, partial)| *partial = sum_block(block)));
+                }
+            });
+        }
+        let mut accumulator = Accumulator::new(self.summation, size);
+        partials.iter().for_each(|partial| accumulator.add(partial));
+        accumulator.total()
+    }
+
+    // `sum of w^2 g g^T` over events, with `g` the gradient of each event's nll; the middle of the sandwich covariance.
+    // the once-per-dataset `extended` term is left out on purpose: it is not a sum over events, so it adds nothing to the
+    // variance of the score, and with the events' `-ln nu` this gives `sum of w^2` as the variance of a fitted yield
+    pub fn gradient_products(&self, x: &[f64]) -> Matrix {
+        let n = x.len();
+        let prepared = self.likelihood.prepare(x, Order::Gradient);
+        let packed = self.reduce(n * (n + 1) / 2, |event, weight, terms| {
+            let mut gradient = vec![0.0; n];
+            self.likelihood.gradient(&prepared, event, &mut gradient);
+            // scaled by the weight once here and once more by `reduce`
+            let mut k = 0;
+            for i in 0..n {
+                for j in i..n {
+                    terms[k] = weight * gradient[i] * gradient[j];
+                    k += 1;
+                }
+            }
+        });
+        Matrix::from_packed(n, &packed)
+    }
+}
+
+impl<L: Likelihood> Objective for Nll<'_, L> {
+    fn dimension(&self) -> usize {
+        self.likelihood.num_parameters()
+    }
+
+    fn value(&self, x: &[f64]) -> f64 {
+        let n = x.len();
+        let extended = self.likelihood.extended(x, &mut vec![0.0; n], &mut vec![0.0; n * (n + 1) / 2]);
+        let prepared = self.likelihood.prepare(x, Order::Value);
+        let totals = self.reduce(1, |event, _, terms| terms[0] = self.likelihood.value(&prepared, event));
+        extended + totals[0]
+    }
+
+    fn gradient(&self, x: &[f64], gradient: &mut [f64]) -> f64 {
+        let n = x.len();
+        let value = self.likelihood.extended(x, gradient, &mut vec![0.0; n * (n + 1) / 2]);
+        let prepared = self.likelihood.prepare(x, Order::Gradient);
+        let totals = self.reduce(1 + n, |event, _, terms| {
+            let (value, gradient) = terms.split_at_mut(1);
+            value[0] = self.likelihood.gradient(&prepared, event, gradient);
+        });
+        gradient.iter_mut().zip(&totals[1..]).for_each(|(g, t)| *g += t);
+        value + totals[0]
+    }
+
+    fn hessian(&self, x: &[f64], gradient: &mut [f64], hessian: &mut Matrix) -> f64 {
+        let n = x.len();
+        let mut packed = vec![0.0; n * (n + 1) / 2];
+        let value = self.likelihood.extended(x, gradient, &mut packed);
+        let prepared = self.likelihood.prepare(x, Order::Hessian);
+        let totals = self.reduce(1 + n + packed.len(), |event, _, terms| {
+            let (value, derivatives) = terms.split_at_mut(1);
+            let (gradient, hessian) = derivatives.split_at_mut(n);
+            value[0] = self.likelihood.hessian(&prepared, event, gradient, hessian);
+        });
+        gradient.iter_mut().zip(&totals[1..=n]).for_each(|(g, t)| *g += t);
+        packed.iter_mut().zip(&totals[1 + n..]).for_each(|(h, t)| *h += t);
+        *hessian = Matrix::from_packed(n, &packed);
+        value + totals[0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dataset::Dataset,
+        minimize::Fitter,
+        parameter::Parameter,
+        toys::{Toy, gaussian, gaussian_toy},
+    };
+
+    // a flat density with its yield `nu`, as `define_model(extended(nu))` splits it
+    fn yield_model() -> Model<1, 1, 1> {
+        Model {
+            names: ["nu"],
+            likelihood: |p, _| -p[0].ln(),
+            gradient: |p, _| (-p[0].ln(), [-1.0 / p[0]]),
+            hessian: |p, _| (-p[0].ln(), [-1.0 / p[0]], [1.0 / (p[0] * p[0])]),
+            extended: Some(|p| (p[0], [1.0], [0.0])),
+        }
+    }
+
+    #[test]
+    fn nll_does_not_depend_on_the_thread_count() {
+        let model = gaussian();
+        let dataset = gaussian_toy(50_000, 7);
+        for summation in [Summation::Naive, Summation::Kahan, Summation::Pairwise] {
+            let evaluate = |threads| {
+                let nll = Nll { summation, threads, ..Nll::new(&model, &dataset) };
+                let mut gradient = vec![0.0; 2];
+                let mut hessian = Matrix::zeros(2);
+                let value = nll.hessian(&[1000.1, 0.4], &mut gradient, &mut hessian);
+                (value.to_bits(), gradient.iter().map(|g| g.to_bits()).collect::<Vec<_>>(), (hessian[(0, 1)]).to_bits())
+            };
+            let single = evaluate(1);
+            for threads in [2, 3, 5, 64] {
+                assert_eq!(evaluate(threads), single, "{:?} with {} threads", summation, threads);
+            }
+        }
+    }
+
+    #[test]
+    fn weights_scale_each_event() {
+        let model = gaussian();
+        let events = [[999.5], [1000.25], [1000.75]];
+        let weights = [0.5, 2.0, -0.25];
+        let dataset = Dataset::from_weighted_events(&events, &weights);
+        let nll = Nll::new(&model, &dataset);
+        let parameters = [1000.1, 0.4];
+        let expected: f64 = events.iter().zip(weights).map(|(event, weight)| weight * model.value(&parameters, event)).sum();
+        assert!((nll.value(&parameters) - expected).abs() <= 1e-12 * expected.abs());
+
+        let products = nll.gradient_products(&parameters);
+        let mut gradient = [0.0; 2];
+        let mut expected = Matrix::zeros(2);
+        for (event, weight) in events.iter().zip(weights) {
+            model.gradient(&parameters, event, &mut gradient);
+            (0..2).for_each(|i| (0..2).for_each(|j| expected[(i, j)] += weight * weight * gradient[i] * gradient[j]));
+        }
+        (0..2).for_each(|i| (0..2).for_each(|j| assert!((products[(i, j)] - expected[(i, j)]).abs() <= 1e-12 * expected[(i, j)].abs())));
+    }
+
+    #[test]
+    fn sandwich_errors_do_not_change_with_a_common_weight() {
+        let model = gaussian();
+        let unweighted = gaussian_toy(10_000, 3);
+        let events: Vec<[f64; 1]> = unweighted.events().map(|event| [event[0]]).collect();
+        let weighted = Dataset::from_weighted_events(&events, &vec![2.0; events.len()]);
+        let parameters = [Parameter::from(1000.2), Parameter::from(0.7)];
+        let plain = Fitter::default().fit(&model, &unweighted, &parameters);
+        let doubled = Fitter::default().fit(&model, &weighted, &parameters);
+        assert!(plain.sandwich_covariance.is_none());
+
+        let (errors, doubled_errors, sandwich) = (plain.errors().unwrap(), doubled.errors().unwrap(), doubled.sandwich_errors().unwrap());
+        for i in 0..2 {
+            assert!((doubled.parameters[i] - plain.parameters[i]).abs() <= 1e-6 * errors[i]);
+            assert!((doubled_errors[i] * 2f64.sqrt() - errors[i]).abs() <= 1e-6 * errors[i]);
+            // the sandwich recovers the spread of the events, which for a well-specified model is the parabolic error
+            assert!((sandwich[i] - errors[i]).abs() <= 0.05 * errors[i], "parameter {}: {} against {}", i, sandwich[i], errors[i]);
+        }
+    }
+
+    #[test]
+    fn weighted_yields_have_the_variance_of_their_weights() {
+        let model = yield_model();
+        let mut toy = Toy(11);
+        let events: Vec<[f64; 1]> = (0..400).map(|_| [toy.uniform()]).collect();
+        let weights: Vec<f64> = (0..400).map(|_| 2.0 * toy.uniform() - 0.5).collect();
+        let dataset = Dataset::from_weighted_events(&events, &weights);
+        let result = Fitter { tolerance: 1e-12, ..Fitter::default() }.fit(&model, &dataset, &[Parameter::from(100.0)]);
+
+        let (total, squares) = (weights.iter().sum::<f64>(), weights.iter().map(|w| w * w).sum::<f64>());
+        assert!((result.parameters[0] - total).abs() <= 1e-6 * total);
+        let variance = result.sandwich_covariance.unwrap()[(0, 0)];
+        assert!((variance - squares).abs() <= 1e-6 * squares, "{} against {}", variance, squares);
+    }
+}
@@ -0,0 +1,143 @@
+use std::ops::{Index, IndexMut};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Matrix {
+    size: usize,
+    values: Vec<f64>,
+}
+
+impl Matrix {
+    pub fn zeros(size: usize) -> Self {
+        Self { size, values: vec![0.0; size * size] }
+    }
+
+    pub fn identity(size: usize) -> Self {
+        let mut matrix = Self::zeros(size);
+        for i in 0..size {
+            matrix[(i, i)] = 1.0;
+        }
+        matrix
+    }
+
+    // generated hessians are packed as the upper triangle, row by row
+    pub fn from_packed(size: usize, packed: &[f64]) -> Self {
+        if packed.len() != size * (size + 1) / 2 {
+            panic!("packed matrix of size `{}` must have `{}` entries, but has `{}`", size, size * (size + 1) / 2, packed.len());
+        }
+        let mut matrix = Self::zeros(size);
+        let mut k = 0;
+        for i in 0..size {
+            for j in i..size {
+                matrix[(i, j)] = packed[k];
+                matrix[(j, i)] = packed[k];
+                k += 1;
+            }
+        }
+        matrix
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn submatrix(&self, indices: &[usize]) -> Self {
+        let mut matrix = Self::zeros(indices.len());
+        for (i, row) in indices.iter().enumerate() {
+            for (j, column) in indices.iter().enumerate() {
+                matrix[(i, j)] = self[(*row, *column)];
+            }
+        }
+        matrix
+    }
+
+    pub fn mul_vector(&self, vector: &[f64]) -> Vec<f64> {
+        (0..self.size).map(|i| (0..self.size).map(|j| self[(i, j)] * vector[j]).sum()).collect()
+    }
+
+    pub fn mul_matrix(&self, other: &Self) -> Self {
+        let mut product = Self::zeros(self.size);
+        for i in 0..self.size {
+            for j in 0..self.size {
+                product[(i, j)] = (0..self.size).map(|k| self[(i, k)] * other[(k, j)]).sum();
+            }
+        }
+        product
+    }
+
+    // lower triangular factor `L` with `L * L^T = self`, or `None` if the matrix is not positive definite
+    pub fn cholesky(&self) -> Option<Self> {
+        let n = self.size;
+        let mut lower = Self::zeros(n);
+        for j in 0..n {
+            let mut diagonal = self[(j, j)];
+            for k in 0..j {
+                diagonal -= lower[(j, k)] * lower[(j, k)];
+            }
+            if !diagonal.is_finite() || diagonal <= 0.0 {
+                return None;
+            }
+            let diagonal = diagonal.sqrt();
+            lower[(j, j)] = diagonal;
+            for i in (j + 1)..n {
+                let mut value = self[(i, j)];
+                for k in 0..j {
+                    value -= lower[(i, k)] * lower[(j, k)];
+                }
+                lower[(i, j)] = value / diagonal;
+            }
+        }
+        Some(lower)
+    }
+
+    // solves `L * L^T * x = b` given the cholesky factor `L`
+    pub fn cholesky_solve(&self, b: &[f64]) -> Vec<f64> {
+        let n = self.size;
+        let mut y = vec![0.0; n];
+        for i in 0..n {
+            let mut value = b[i];
+            for k in 0..i {
+                value -= self[(i, k)] * y[k];
+            }
+            y[i] = value / self[(i, i)];
+        }
+        let mut x = vec![0.0; n];
+        for i in (0..n).rev() {
+            let mut value = y[i];
+            for k in (i + 1)..n {
+                value -= self[(k, i)] * x[k];
+            }
+            x[i] = value / self[(i, i)];
+        }
+        x
+    }
+
+    pub fn inverse(&self) -> Option<Self> {
+        let lower = self.cholesky()?;
+        let n = self.size;
+        let mut inverse = Self::zeros(n);
+        let mut unit = vec![0.0; n];
+        for j in 0..n {
+            unit[j] = 1.0;
+            let column = lower.cholesky_solve(&unit);
+            for i in 0..n {
+                inverse[(i, j)] = column[i];
+            }
+            unit[j] = 0.0;
+        }
+        Some(inverse)
+    }
+}
+
+impl Index<(usize, usize)> for Matrix {
+    type Output = f64;
+
+    fn index(&self, (i, j): (usize, usize)) -> &f64 {
+        &self.values[i * self.size + j]
+    }
+}
+
+impl IndexMut<(usize, usize)> for Matrix {
+    fn index_mut(&mut self, (i, j): (usize, usize)) -> &mut f64 {
+        &mut self.values[i * self.size + j]
+    }
+}
@@ -0,0 +1,279 @@
+use crate::{
+    binned::{Binned, Histogram, Integration, Statistic},
+    dataset::Dataset,
+    likelihood::{Likelihood, Nll, Objective},
+    linalg::Matrix,
+    parameter::{Bounds, Parameter},
+    result::FitResult,
+    subspace::Subspace,
+    summation::Summation,
+    transform::Transformed,
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Method {
+    Newton,
+    Bfgs,
+}
+
+#[derive(Clone, Debug)]
+pub struct Fitter {
+    pub method: Method,
+    pub tolerance: f64,
+    pub max_iterations: usize,
+    // threads the nll is summed over; the result is identical for any count
+    pub threads: usize,
+    pub summation: Summation,
+}
+
+#[derive(Clone, Debug)]
+pub struct Minimum {
+    pub parameters: Vec<f64>,
+    pub nll: f64,
+    pub edm: f64,
+    pub iterations: usize,
+    pub converged: bool,
+}
+
+impl Default for Fitter {
+    fn default() -> Self {
+        Self { method: Method::Newton, tolerance: 1e-6, max_iterations: 1000, threads: 1, summation: Summation::Kahan }
+    }
+}
+
+impl Fitter {
+    pub fn fit<L: Likelihood>(&self, likelihood: &L, dataset: &Dataset, parameters: &[Parameter]) -> FitResult {
+        let nll = Nll { threads: self.threads, summation: self.summation, ..Nll::new(likelihood, dataset) };
+        let mut result = self.fit_objective(&nll, likelihood.parameter_names(), parameters);
+        if dataset.is_weighted() {
+            result.set_gradient_products(&nll.gradient_products(&result.parameters));
+        }
+        result
+    }
+
+    pub fn fit_binned<L: Likelihood>(&self, likelihood: &L, histogram: &Histogram, parameters: &[Parameter], integration: Integration, statistic: Statistic) -> FitResult {
+        let mut binned = Binned::new(likelihood, histogram, integration, statistic);
+        binned.summation = self.summation;
+        self.fit_objective(&binned, likelihood.parameter_names(), parameters)
+    }
+
+    fn fit_objective(&self, objective: &impl Objective, names: Vec<String>, parameters: &[Parameter]) -> FitResult {
+        if parameters.len() != objective.dimension() {
+            panic!("model takes `{}` parameters, but was given `{}`", objective.dimension(), parameters.len());
+        }
+        for (name, parameter) in names.iter().zip(parameters) {
+            if !parameter.bounds().contains(parameter.value()) {
+                panic!("initial value of parameter `{}` lies outside its bounds", name);
+            }
+        }
+        let bounds: Vec<Bounds> = parameters.iter().map(|p| p.bounds()).collect();
+        let transformed = Transformed { objective, bounds: &bounds };
+        let initial: Vec<f64> = parameters.iter().map(|p| p.value()).collect();
+        let floating = (0..parameters.len()).filter(|i| !parameters[*i].is_fixed()).collect();
+        let subspace = Subspace { objective: &transformed, values: transformed.to_internal(&initial), floating };
+        let mut minimum = self.minimize(&subspace, subspace.restrict(&subspace.values));
+        minimum.parameters = transformed.to_external(&subspace.expand(&minimum.parameters));
+        let fixed = parameters.iter().map(|p| p.is_fixed()).collect();
+        FitResult::new(names, bounds, fixed, minimum, objective)
+    }
+
+    pub fn minimize(&self, objective: &impl Objective, start: Vec<f64>) -> Minimum {
+        match self.method {
+            Method::Newton => self.newton(objective, start),
+            Method::Bfgs => self.bfgs(objective, start),
+        }
+    }
+
+    fn newton(&self, objective: &impl Objective, mut x: Vec<f64>) -> Minimum {
+        let n = objective.dimension();
+        let mut gradient = vec![0.0; n];
+        let mut hessian = Matrix::zeros(n);
+        let mut value = objective.hessian(&x, &mut gradient, &mut hessian);
+        let mut edm = f64::INFINITY;
+
+        // steps taken, which is less than `max_iterations` when the search stops early
+        let mut iterations = 0;
+        while iterations < self.max_iterations {
+            if !value.is_finite() {
+                break;
+            }
+            let step = damped_newton_step(&hessian, &gradient);
+            edm = -0.5 * dot(&gradient, &step);
+            if edm < self.tolerance {
+                return Minimum { parameters: x, nll: value, edm, iterations, converged: true };
+            }
+            match line_search(objective, &x, value, &gradient, &step) {
+                Some(next) => x = next,
+                None => break,
+            }
+            value = objective.hessian(&x, &mut gradient, &mut hessian);
+            iterations += 1;
+        }
+        Minimum { parameters: x, nll: value, edm, iterations, converged: false }
+    }
+
+    fn bfgs(&self, objective: &impl Objective, mut x: Vec<f64>) -> Minimum {
+        let n = objective.dimension();
+        let mut gradient = vec![0.0; n];
+        let mut value = objective.gradient(&x, &mut gradient);
+        let mut inverse = Matrix::identity(n);
+        let mut scaled = false;
+        let mut edm = f64::INFINITY;
+
+        let mut iterations = 0;
+        while iterations < self.max_iterations {
+            if !value.is_finite() {
+                break;
+            }
+            let mut step: Vec<f64> = inverse.mul_vector(&gradient).iter().map(|s| -s).collect();
+            edm = -0.5 * dot(&gradient, &step);
+            if edm < self.tolerance {
+                return Minimum { parameters: x, nll: value, edm, iterations, converged: true };
+            }
+            if dot(&gradient, &step) >= 0.0 {
+                inverse = Matrix::identity(n);
+                step = gradient.iter().map(|g| -g).collect();
+            }
+            let Some(next) = line_search(objective, &x, value, &gradient, &step) else {
+                break;
+            };
+            let mut next_gradient = vec![0.0; n];
+            let next_value = objective.gradient(&next, &mut next_gradient);
+
+            let s: Vec<f64> = next.iter().zip(&x).map(|(a, b)| a - b).collect();
+            let y: Vec<f64> = next_gradient.iter().zip(&gradient).map(|(a, b)| a - b).collect();
+            let sy = dot(&s, &y);
+            if sy > f64::EPSILON * dot(&s, &s).sqrt() * dot(&y, &y).sqrt() {
+                if !scaled {
+                    inverse = Matrix::identity(n);
+                    let scale = sy / dot(&y, &y);
+                    (0..n).for_each(|i| inverse[(i, i)] = scale);
+                    scaled = true;
+                }
+                bfgs_update(&mut inverse, &s, &y, sy);
+            }
+            x = next;
+            value = next_value;
+            gradient = next_gradient;
+            iterations += 1;
+        }
+        Minimum { parameters: x, nll: value, edm, iterations, converged: false }
+    }
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+// newton step `-H^-1 g`, shifting the diagonal until the hessian is positive definite
+fn damped_newton_step(hessian: &Matrix, gradient: &[f64]) -> Vec<f64> {
+    let n = hessian.size();
+    let scale = (0..n).map(|i| hessian[(i, i)].abs()).fold(1e-8, f64::max);
+    let mut shift = 0.0;
+    loop {
+        let mut shifted = hessian.clone();
+        (0..n).for_each(|i| shifted[(i, i)] += shift);
+        if let Some(lower) = shifted.cholesky() {
+            return lower.cholesky_solve(gradient).iter().map(|s| -s).collect();
+        }
+        if shift > 1e12 * scale {
+            return gradient.iter().map(|g| -g / scale).collect();
+        }
+        shift = if shift == 0.0 {
+            1e-6 * scale
+        } else {
+            shift * 10.0
+        };
+    }
+}
+
+// backtracking search along `step` for a sufficient (armijo) decrease
+fn line_search(objective: &impl Objective, x: &[f64], value: f64, gradient: &[f64], step: &[f64]) -> Option<Vec<f64>> {
+    let slope = dot(gradient, step);
+    let mut alpha = 1.0;
+    for _ in 0..50 {
+        let next: Vec<f64> = x.iter().zip(step).map(|(x, s)| x + alpha * s).collect();
+        let next_value = objective.value(&next);
+        if next_value.is_finite() && next_value <= value + 1e-4 * alpha * slope {
+            return Some(next);
+        }
+        alpha *= 0.5;
+    }
+    None
+}
+
+// inverse hessian update `(I - r s y^T) B (I - r y s^T) + r s s^T` with `r = 1 / s^T y`
+fn bfgs_update(inverse: &mut Matrix, s: &[f64], y: &[f64], sy: f64) {
+    let n = s.len();
+    let by = inverse.mul_vector(y);
+    let yby = dot(y, &by);
+    for i in 0..n {
+        for j in 0..n {
+            inverse[(i, j)] += ((sy + yby) * s[i] * s[j]) / (sy * sy) - (by[i] * s[j] + s[i] * by[j]) / sy;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toys::{gaussian, gaussian_toy};
+
+    // rises along `x` up to a cliff at -1.5, below which it is undefined
+    struct Ledge;
+
+    impl Objective for Ledge {
+        fn dimension(&self) -> usize {
+            1
+        }
+
+        fn value(&self, x: &[f64]) -> f64 {
+            if x[0] >= -1.5 {
+                x[0]
+            } else {
+                f64::NAN
+            }
+        }
+
+        fn gradient(&self, x: &[f64], gradient: &mut [f64]) -> f64 {
+            gradient[0] = 1.0;
+            self.value(x)
+        }
+
+        fn hessian(&self, x: &[f64], gradient: &mut [f64], hessian: &mut Matrix) -> f64 {
+            hessian[(0, 0)] = 1.0;
+            self.gradient(x, gradient)
+        }
+    }
+
+    #[test]
+    fn both_methods_recover_the_gaussian_estimates() {
+        let dataset = gaussian_toy(2000, 5);
+        let events: Vec<f64> = dataset.events().map(|event| event[0]).collect();
+        let mean = events.iter().sum::<f64>() / events.len() as f64;
+        let width = (events.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / events.len() as f64).sqrt();
+        for method in [Method::Newton, Method::Bfgs] {
+            let fitter = Fitter { method, tolerance: 1e-12, ..Fitter::default() };
+            let result = fitter.fit(&gaussian(), &dataset, &[Parameter::from(1000.3), Parameter::from(0.8)]);
+            assert!(result.converged, "{:?}", method);
+            assert!(result.iterations < fitter.max_iterations, "{:?}", method);
+            assert!(result.edm < 1e-12, "{:?}", method);
+            assert!((result.parameters[0] - mean).abs() <= 1e-6 * width, "{:?} mean {} against {}", method, result.parameters[0], mean);
+            assert!((result.parameters[1] - width).abs() <= 1e-6 * width, "{:?} width {} against {}", method, result.parameters[1], width);
+        }
+    }
+
+    #[test]
+    fn stopped_searches_report_the_steps_taken() {
+        for method in [Method::Newton, Method::Bfgs] {
+            let minimum = Fitter { method, ..Fitter::default() }.minimize(&Ledge, vec![0.0]);
+            assert!(!minimum.converged, "{:?}", method);
+            assert_eq!((minimum.parameters, minimum.iterations), (vec![-1.5], 2), "{:?}", method);
+
+            let fitter = Fitter { method, max_iterations: 1, ..Fitter::default() };
+            let result = fitter.fit(&gaussian(), &gaussian_toy(100, 5), &[Parameter::from(1003.0), Parameter::from(2.0)]);
+            assert!(!result.converged, "{:?}", method);
+            assert_eq!(result.iterations, 1, "{:?}", method);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bounds {
+    None,
+    Lower(f64),
+    Upper(f64),
+    Both(f64, f64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FloatingParameter {
+    pub value: f64,
+    pub bounds: Bounds,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Parameter {
+    Floating(FloatingParameter),
+    Fixed(f64),
+}
+
+impl Parameter {
+    pub fn floating(value: f64, bounds: Bounds) -> Self {
+        Self::Floating(FloatingParameter { value, bounds })
+    }
+
+    pub fn value(&self) -> f64 {
+        match self {
+            Self::Floating(f) => f.value,
+            Self::Fixed(value) => *value,
+        }
+    }
+
+    pub fn bounds(&self) -> Bounds {
+        match self {
+            Self::Floating(f) => f.bounds,
+            Self::Fixed(_) => Bounds::None,
+        }
+    }
+
+    pub fn is_fixed(&self) -> bool {
+        matches!(self, Self::Fixed(_))
+    }
+}
+
+impl From<f64> for Parameter {
+    fn from(value: f64) -> Self {
+        Self::floating(value, Bounds::None)
+    }
+}
+
+impl Bounds {
+    pub fn contains(&self, value: f64) -> bool {
+        match *self {
+            Self::None => true,
+            Self::Lower(lower) => value >= lower,
+            Self::Upper(upper) => value <= upper,
+            Self::Both(lower, upper) => value >= lower && value <= upper,
+        }
+    }
+
+    // the internal value is unbounded, and maps onto the external value through
+    // `a + (b - a) (sin u + 1) / 2` for two bounds and `a - 1 + sqrt(u^2 + 1)` for one
+    pub fn to_external(&self, internal: f64) -> f64 {
+        match *self {
+            Self::None => internal,
+            Self::Lower(lower) => lower - 1.0 + (internal * internal + 1.0).sqrt(),
+            Self::Upper(upper) => upper + 1.0 - (internal * internal + 1.0).sqrt(),
+            Self::Both(lower, upper) => lower + 0.5 * (upper - lower) * (internal.sin() + 1.0),
+        }
+    }
+
+    pub fn to_internal(&self, external: f64) -> f64 {
+        if !self.contains(external) {
+            return f64::NAN;
+        }
+        match *self {
+            Self::None => external,
+            Self::Lower(lower) => ((external - lower + 1.0).powi(2) - 1.0).sqrt(),
+            Self::Upper(upper) => ((upper - external + 1.0).powi(2) - 1.0).sqrt(),
+            Self::Both(lower, upper) => (2.0 * (external - lower) / (upper - lower) - 1.0).asin(),
+        }
+    }
+
+    // first and second derivatives of the external value with respect to the internal one
+    pub fn derivatives(&self, internal: f64) -> (f64, f64) {
+        match *self {
+            Self::None => (1.0, 0.0),
+            Self::Lower(_) => {
+                let root = (internal * internal + 1.0).sqrt();
+                (internal / root, 1.0 / (root * root * root))
+            }
+            Self::Upper(_) => {
+                let root = (internal * internal + 1.0).sqrt();
+                (-internal / root, -1.0 / (root * root * root))
+            }
+            Self::Both(lower, upper) => (0.5 * (upper - lower) * internal.cos(), -0.5 * (upper - lower) * internal.sin()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDS: [Bounds; 4] = [Bounds::None, Bounds::Lower(-1.0), Bounds::Upper(2.0), Bounds::Both(-1.0, 2.0)];
+
+    #[test]
+    fn transformations_round_trip_inside_the_bounds() {
+        for bounds in BOUNDS {
+            for external in [-1.0, -0.3, 0.0, 1.5, 2.0] {
+                let internal = bounds.to_internal(external);
+                assert!((bounds.to_external(internal) - external).abs() <= 1e-12, "{:?} at {}", bounds, external);
+            }
+            for internal in [-40.0, -1.0, 0.0, 0.5, 40.0] {
+                assert!(bounds.contains(bounds.to_external(internal)), "{:?} at {}", bounds, internal);
+            }
+        }
+        assert!(Bounds::Lower(-1.0).to_internal(-1.5).is_nan());
+        assert!(Bounds::Upper(2.0).to_internal(2.5).is_nan());
+        assert!(Bounds::Both(-1.0, 2.0).to_internal(2.5).is_nan());
+    }
+
+    #[test]
+    fn derivatives_match_differences() {
+        let step = 1e-5;
+        for bounds in BOUNDS {
+            for internal in [-2.0, -0.4, 0.0, 0.7, 3.0] {
+                let (first, second) = bounds.derivatives(internal);
+                let (up, down) = (bounds.to_external(internal + step), bounds.to_external(internal - step));
+                let (up_first, down_first) = (bounds.derivatives(internal + step).0, bounds.derivatives(internal - step).0);
+                assert!((first - (up - down) / (2.0 * step)).abs() <= 1e-8, "{:?} at {}", bounds, internal);
+                assert!((second - (up_first - down_first) / (2.0 * step)).abs() <= 1e-8, "{:?} at {}", bounds, internal);
+            }
+        }
+    }
+}
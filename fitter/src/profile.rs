@@ -0,0 +1,165 @@
+use crate::{
+    dataset::Dataset,
+    likelihood::{Likelihood, Nll, Objective},
+    minimize::{Fitter, Method},
+    result::FitResult,
+    subspace::Subspace,
+    transform::Transformed,
+};
+
+#[derive(Clone, Debug)]
+pub struct Interval {
+    pub name: String,
+    pub value: f64,
+    // `None` if the profile does not rise by `delta` on that side
+    pub lower: Option<f64>,
+    pub upper: Option<f64>,
+}
+
+impl Interval {
+    pub fn lower_error(&self) -> Option<f64> {
+        self.lower.map(|lower| lower - self.value)
+    }
+
+    pub fn upper_error(&self) -> Option<f64> {
+        self.upper.map(|upper| upper - self.value)
+    }
+}
+
+impl Fitter {
+    // asymmetric intervals where the profiled nll rises by `delta` above the minimum (0.5 for one sigma)
+    pub fn intervals<L: Likelihood>(&self, likelihood: &L, dataset: &Dataset, result: &FitResult, names: &[&str], delta: f64) -> Vec<Interval> {
+        let nll = Nll { threads: self.threads, summation: self.summation, ..Nll::new(likelihood, dataset) };
+        let transformed = Transformed { objective: &nll, bounds: &result.bounds };
+        let profiler = Fitter { method: Method::Bfgs, ..self.clone() };
+        names
+            .iter()
+            .map(|name| {
+                let index = result.index(name).unwrap_or_else(|| panic!("unknown parameter `{}`", name));
+                let value = result.parameters[index];
+                if result.fixed[index] {
+                    return Interval { name: name.to_string(), value, lower: None, upper: None };
+                }
+                let scale = result.error(name).filter(|e| e.is_finite() && *e > 0.0).unwrap_or(value.abs().max(1.0) * 0.01);
+                let lower = profiler.crossing(&transformed, result, index, -scale, delta).map(|t| value - t);
+                let upper = profiler.crossing(&transformed, result, index, scale, delta).map(|t| value + t);
+                Interval { name: name.to_string(), value, lower, upper }
+            })
+            .collect()
+    }
+
+    // distance along `step` at which the profiled nll crosses `result.nll + delta`
+    fn crossing<O: Objective>(&self, objective: &Transformed<O>, result: &FitResult, index: usize, step: f64, delta: f64) -> Option<f64> {
+        let internal = objective.to_internal(&result.parameters);
+        let floating: Vec<usize> = (0..internal.len()).filter(|i| *i != index && !result.fixed[*i]).collect();
+        let mut start: Vec<f64> = floating.iter().map(|i| internal[*i]).collect();
+        let rise = |distance: f64, start: &mut Vec<f64>| {
+            let value = result.bounds[index].to_internal(result.parameters[index] + distance * step.signum());
+            if value.is_nan() {
+                return f64::NAN;
+            }
+            let mut values = internal.clone();
+            values[index] = value;
+            let profile = Subspace { objective, values, floating: floating.clone() };
+            let minimum = self.minimize(&profile, start.clone());
+            if minimum.nll.is_finite() {
+                *start = minimum.parameters;
+            }
+            minimum.nll - result.nll - delta
+        };
+
+        let (mut a, mut fa) = (0.0, -delta);
+        let (mut b, mut fb) = (step.abs(), rise(step.abs(), &mut start));
+        let mut expansions = 0;
+        while fb < 0.0 {
+            if expansions == 30 {
+                return None;
+            }
+            (a, fa) = (b, fb);
+            b *= 2.0;
+            fb = rise(b, &mut start);
+            expansions += 1;
+        }
+        if !fb.is_finite() {
+            return None;
+        }
+
+        // illinois false position on the bracket `[a, b]`
+        let mut side = 0;
+        for _ in 0..100 {
+            let c = (a * fb - b * fa) / (fb - fa);
+            let fc = rise(c, &mut start);
+            if !fc.is_finite() {
+                return None;
+            }
+            if fc.abs() < 1e-4 * delta || (b - a).abs() < 1e-10 * b.abs() {
+                return Some(c);
+            }
+            if fc < 0.0 {
+                (a, fa) = (c, fc);
+                if side == -1 {
+                    fb *= 0.5;
+                }
+                side = -1;
+            } else {
+                (b, fb) = (c, fc);
+                if side == 1 {
+                    fa *= 0.5;
+                }
+                side = 1;
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        minimize::Fitter,
+        parameter::{Bounds, Parameter},
+        toys::{gaussian, gaussian_toy},
+    };
+
+    #[test]
+    fn gaussian_intervals_match_the_profile() {
+        let dataset = gaussian_toy(400, 13);
+        let events = dataset.len() as f64;
+        let fitter = Fitter { tolerance: 1e-12, ..Fitter::default() };
+        let result = fitter.fit(&gaussian(), &dataset, &[Parameter::from(1000.1), Parameter::from(0.6)]);
+        let (mean, width) = (result.parameters[0], result.parameters[1]);
+
+        for delta in [0.5, 2.0] {
+            let intervals = fitter.intervals(&gaussian(), &dataset, &result, &["mu", "sigma"], delta);
+            // profiling `sigma` gives `N ln(sigma) = N/2 ln(s^2 + (mu - m)^2)`, which crosses where `(mu - m)^2 = s^2 (exp(2 delta / N) - 1)`
+            let half_width = width * ((2.0 * delta / events).exp() - 1.0).sqrt();
+            let parabolic = result.error("mu").unwrap() * (2.0 * delta).sqrt();
+            for error in [-intervals[0].lower_error().unwrap(), intervals[0].upper_error().unwrap()] {
+                assert!((error - half_width).abs() <= 1e-3 * half_width, "mu error {} against {}", error, half_width);
+                assert!((error - parabolic).abs() <= 0.01 * parabolic);
+            }
+
+            // `mu` stays at the mean, so `sigma` crosses where `N (ln(sigma / s) + s^2 / (2 sigma^2) - 1/2) = delta`
+            let rise = |sigma: f64| events * ((sigma / width).ln() + width * width / (2.0 * sigma * sigma) - 0.5);
+            let (lower, upper) = (intervals[1].lower.unwrap(), intervals[1].upper.unwrap());
+            assert!((rise(lower) - delta).abs() <= 1e-3 * delta && (rise(upper) - delta).abs() <= 1e-3 * delta);
+            assert!(width - lower < upper - width);
+            assert_eq!((intervals[0].value, intervals[1].value), (mean, width));
+        }
+    }
+
+    #[test]
+    fn intervals_stop_at_bounds_and_skip_fixed_parameters() {
+        let dataset = gaussian_toy(400, 13);
+        let fitter = Fitter::default();
+        let free = fitter.fit(&gaussian(), &dataset, &[Parameter::from(1000.1), Parameter::from(0.6)]);
+        let ceiling = free.parameters[1] + 0.1 * free.error("sigma").unwrap();
+        let parameters = [Parameter::Fixed(free.parameters[0]), Parameter::floating(0.4, Bounds::Both(0.1, ceiling))];
+        let result = fitter.fit(&gaussian(), &dataset, &parameters);
+
+        let intervals = fitter.intervals(&gaussian(), &dataset, &result, &["mu", "sigma"], 0.5);
+        assert_eq!((intervals[0].lower, intervals[0].upper), (None, None));
+        assert!(intervals[1].lower.is_some());
+        assert_eq!(intervals[1].upper, None);
+    }
+}
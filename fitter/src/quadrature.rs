@@ -0,0 +1,126 @@
+use std::f64::consts::PI;
+
+// nodes and weights of the `points`-point gauss-legendre rule on [-1, 1]
+pub fn gauss_legendre(points: usize) -> (Vec<f64>, Vec<f64>) {
+    let mut nodes = vec![0.0; points];
+    let mut weights = vec![0.0; points];
+    for i in 0..points.div_ceil(2) {
+        // newton iteration on the legendre polynomial, starting from the chebyshev approximation
+        let mut x = (PI * (i as f64 + 0.75) / (points as f64 + 0.5)).cos();
+        let mut derivative = 0.0;
+        for _ in 0..100 {
+            let (mut p0, mut p1) = (1.0, x);
+            for k in 2..=points {
+                (p0, p1) = (p1, ((2 * k - 1) as f64 * x * p1 - (k - 1) as f64 * p0) / k as f64);
+            }
+            let value = if points == 1 {
+                x
+            } else {
+                p1
+            };
+            let previous = if points == 1 {
+                1.0
+            } else {
+                p0
+            };
+            derivative = points as f64 * (x * value - previous) / (x * x - 1.0);
+            let step = value / derivative;
+            x -= step;
+            if step.abs() < 1e-15 {
+                break;
+            }
+        }
+        if points == 1 {
+            derivative = 1.0;
+        }
+        nodes[i] = x;
+        nodes[points - 1 - i] = -x;
+        weights[i] = 2.0 / ((1.0 - x * x) * derivative * derivative);
+        weights[points - 1 - i] = weights[i];
+    }
+    (nodes, weights)
+}
+
+// tensor-product gauss-legendre grid over a box, as `(point, weight)` pairs
+pub fn grid<const M: usize>(domain: &[(f64, f64); M], points: usize) -> Vec<([f64; M], f64)> {
+    if points == 0 {
+        panic!("gauss-legendre integration needs at least one point");
+    }
+    let (nodes, weights) = gauss_legendre(points);
+    product_rule(domain, &nodes, &weights).into_iter().map(|(point, weight)| (point.try_into().unwrap(), weight)).collect()
+}
+
+// the tensor product of a rule on [-1, 1] over a box of any dimension
+pub(crate) fn product_rule(domain: &[(f64, f64)], nodes: &[f64], weights: &[f64]) -> Vec<(Vec<f64>, f64)> {
+    let m = domain.len();
+    let mut grid = Vec::with_capacity(nodes.len().pow(m as u32));
+    let mut indices = vec![0; m];
+    loop {
+        let mut point = vec![0.0; m];
+        let mut weight = 1.0;
+        for (d, (lower, upper)) in domain.iter().enumerate() {
+            let half = 0.5 * (upper - lower);
+            point[d] = lower + half * (nodes[indices[d]] + 1.0);
+            weight *= half * weights[indices[d]];
+        }
+        grid.push((point, weight));
+
+        let Some(d) = (0..m).find(|d| indices[*d] + 1 < nodes.len()) else {
+            return grid;
+        };
+        indices[d] += 1;
+        indices[..d].fill(0);
+    }
+}
+
+// `ln N` alone, for where the derivatives of the normalization are not needed
+pub fn log_integral<const M: usize>(density: impl Fn([f64; M]) -> f64, domain: &[(f64, f64); M], points: usize) -> f64 {
+    grid(domain, points).into_iter().map(|(point, weight)| weight * density(point)).sum::<f64>().ln()
+}
+
+// `ln N` and its derivatives for `N(p) = integral of density(p, x) dx`, given the density with its gradient and packed hessian
+pub fn log_normalization<const N: usize, const M: usize, const H: usize>(
+    density: impl Fn([f64; M]) -> (f64, [f64; N], [f64; H]),
+    domain: &[(f64, f64); M],
+    points: usize,
+) -> (f64, [f64; N], [f64; H]) {
+    let mut integral = 0.0;
+    let mut gradient = [0.0; N];
+    let mut hessian = [0.0; H];
+    for (point, weight) in grid(domain, points) {
+        let (value, g, h) = density(point);
+        integral += weight * value;
+        gradient.iter_mut().zip(g).for_each(|(a, g)| *a += weight * g);
+        hessian.iter_mut().zip(h).for_each(|(a, h)| *a += weight * h);
+    }
+
+    // d2 ln N / dp_i dp_j = N_ij / N - N_i N_j / N^2
+    let mut k = 0;
+    for i in 0..N {
+        for j in i..N {
+            hessian[k] = hessian[k] / integral - gradient[i] * gradient[j] / (integral * integral);
+            k += 1;
+        }
+    }
+    gradient.iter_mut().for_each(|g| *g /= integral);
+    (integral.ln(), gradient, hessian)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_integrates_polynomials_exactly() {
+        // `points` nodes are exact up to degree `2 points - 1` in each dimension
+        let value = log_integral(|[x, y]| x.powi(5) * y * y + 1.0, &[(0.0, 2.0), (-1.0, 1.0)], 3).exp();
+        assert!((value - (64.0 / 6.0 * 2.0 / 3.0 + 4.0)).abs() < 1e-13, "{}", value);
+        assert_eq!(grid(&[(0.0, 1.0)], 1), [([0.5], 1.0)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "needs at least one point")]
+    fn grid_needs_a_point() {
+        log_integral(|[x]| x, &[(0.0, 1.0)], 0);
+    }
+}
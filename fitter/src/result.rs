@@ -0,0 +1,158 @@
+use crate::{likelihood::Objective, linalg::Matrix, minimize::Minimum, parameter::Bounds};
+
+#[derive(Clone, Debug)]
+pub struct FitResult {
+    pub names: Vec<String>,
+    pub parameters: Vec<f64>,
+    pub bounds: Vec<Bounds>,
+    pub fixed: Vec<bool>,
+    pub nll: f64,
+    pub edm: f64,
+    pub iterations: usize,
+    pub converged: bool,
+    pub hessian: Matrix,
+    // zero for fixed parameters, `None` if the floating hessian at the minimum is not positive definite
+    pub covariance: Option<Matrix>,
+    // `H^-1 C H^-1` with `C` from the weighted per-event gradients, for fits to weighted datasets
+    pub sandwich_covariance: Option<Matrix>,
+}
+
+impl FitResult {
+    pub(crate) fn new(names: Vec<String>, bounds: Vec<Bounds>, fixed: Vec<bool>, minimum: Minimum, objective: &impl Objective) -> Self {
+        let n = minimum.parameters.len();
+        let mut gradient = vec![0.0; n];
+        let mut hessian = Matrix::zeros(n);
+        objective.hessian(&minimum.parameters, &mut gradient, &mut hessian);
+
+        let floating: Vec<usize> = (0..n).filter(|i| !fixed[*i]).collect();
+        let covariance = hessian.submatrix(&floating).inverse().map(|inverse| {
+            let mut covariance = Matrix::zeros(n);
+            for (i, row) in floating.iter().enumerate() {
+                for (j, column) in floating.iter().enumerate() {
+                    covariance[(*row, *column)] = inverse[(i, j)];
+                }
+            }
+            covariance
+        });
+        let Minimum { parameters, nll, edm, iterations, converged } = minimum;
+        Self { names, parameters, bounds, fixed, nll, edm, iterations, converged, hessian, covariance, sandwich_covariance: None }
+    }
+
+    pub(crate) fn set_gradient_products(&mut self, products: &Matrix) {
+        self.sandwich_covariance = self.covariance.as_ref().map(|inverse| inverse.mul_matrix(products).mul_matrix(inverse));
+    }
+
+    pub fn index(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+
+    pub fn value(&self, name: &str) -> Option<f64> {
+        Some(self.parameters[self.index(name)?])
+    }
+
+    pub fn errors(&self) -> Option<Vec<f64>> {
+        let covariance = self.covariance.as_ref()?;
+        Some((0..covariance.size()).map(|i| covariance[(i, i)].sqrt()).collect())
+    }
+
+    pub fn sandwich_errors(&self) -> Option<Vec<f64>> {
+        let covariance = self.sandwich_covariance.as_ref()?;
+        Some((0..covariance.size()).map(|i| covariance[(i, i)].sqrt()).collect())
+    }
+
+    pub fn error(&self, name: &str) -> Option<f64> {
+        let i = self.index(name)?;
+        Some(self.covariance.as_ref()?[(i, i)].sqrt())
+    }
+
+    pub fn correlation_matrix(&self) -> Option<Matrix> {
+        let covariance = self.covariance.as_ref()?;
+        let n = covariance.size();
+        let mut correlation = Matrix::zeros(n);
+        for i in 0..n {
+            for j in 0..n {
+                correlation[(i, j)] = correlation_of(covariance, i, j);
+            }
+        }
+        Some(correlation)
+    }
+
+    pub fn correlation(&self, first: &str, second: &str) -> Option<f64> {
+        let (i, j) = (self.index(first)?, self.index(second)?);
+        let covariance = self.covariance.as_ref()?;
+        Some(correlation_of(covariance, i, j))
+    }
+}
+
+// fixed parameters are uncorrelated with everything
+fn correlation_of(covariance: &Matrix, i: usize, j: usize) -> f64 {
+    let variance = covariance[(i, i)] * covariance[(j, j)];
+    if variance == 0.0 {
+        0.0
+    } else {
+        covariance[(i, j)] / variance.sqrt()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        minimize::Fitter,
+        parameter::Parameter,
+        toys::{gaussian, gaussian_toy},
+    };
+
+    // `x^2 - y^2`, whose hessian is not positive definite anywhere
+    struct Saddle;
+
+    impl Objective for Saddle {
+        fn dimension(&self) -> usize {
+            2
+        }
+
+        fn value(&self, x: &[f64]) -> f64 {
+            x[0] * x[0] - x[1] * x[1]
+        }
+
+        fn gradient(&self, x: &[f64], gradient: &mut [f64]) -> f64 {
+            gradient.copy_from_slice(&[2.0 * x[0], -2.0 * x[1]]);
+            self.value(x)
+        }
+
+        fn hessian(&self, x: &[f64], gradient: &mut [f64], hessian: &mut Matrix) -> f64 {
+            *hessian = Matrix::from_packed(2, &[2.0, 0.0, -2.0]);
+            self.gradient(x, gradient)
+        }
+    }
+
+    #[test]
+    fn gaussian_errors_match_their_closed_form() {
+        let dataset = gaussian_toy(5000, 9);
+        let fitter = Fitter { tolerance: 1e-12, ..Fitter::default() };
+        let result = fitter.fit(&gaussian(), &dataset, &[Parameter::from(1000.1), Parameter::from(0.6)]);
+        // at the estimates the hessian is `diag(N / sigma^2, 2 N / sigma^2)`
+        let (width, events) = (result.value("sigma").unwrap(), dataset.len() as f64);
+        let expected = [width / events.sqrt(), width / (2.0 * events).sqrt()];
+        let errors = result.errors().unwrap();
+        for (i, name) in ["mu", "sigma"].iter().enumerate() {
+            assert_eq!(result.error(name), Some(errors[i]));
+            assert!((errors[i] - expected[i]).abs() <= 1e-6 * expected[i], "{}: {} against {}", name, errors[i], expected[i]);
+        }
+
+        let correlation = result.correlation_matrix().unwrap();
+        assert_eq!((correlation[(0, 0)], correlation[(1, 1)]), (1.0, 1.0));
+        assert!(correlation[(0, 1)].abs() < 1e-6 && correlation[(0, 1)] == correlation[(1, 0)]);
+        assert_eq!(result.correlation("mu", "sigma"), Some(correlation[(0, 1)]));
+        assert_eq!((result.index("sigma"), result.value("tau"), result.error("tau")), (Some(1), None, None));
+    }
+
+    #[test]
+    fn indefinite_hessians_have_no_covariance() {
+        let minimum = Minimum { parameters: vec![0.0, 0.0], nll: 0.0, edm: 0.0, iterations: 0, converged: true };
+        let result = FitResult::new(vec!["x".to_string(), "y".to_string()], vec![Bounds::None; 2], vec![false; 2], minimum, &Saddle);
+        assert!(result.covariance.is_none());
+        assert_eq!((result.errors(), result.error("x"), result.correlation("x", "y")), (None, None, None));
+        assert!(result.correlation_matrix().is_none());
+    }
+}
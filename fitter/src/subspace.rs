@@ -0,0 +1,92 @@
+use crate::{likelihood::Objective, linalg::Matrix};
+
+// objective over the `floating` entries of `values`, with all other entries held fixed
+pub struct Subspace<'a, O: Objective> {
+    pub objective: &'a O,
+    pub values: Vec<f64>,
+    pub floating: Vec<usize>,
+}
+
+impl<O: Objective> Subspace<'_, O> {
+    pub fn expand(&self, x: &[f64]) -> Vec<f64> {
+        let mut full = self.values.clone();
+        self.floating.iter().zip(x).for_each(|(i, x)| full[*i] = *x);
+        full
+    }
+
+    pub fn restrict(&self, full: &[f64]) -> Vec<f64> {
+        self.floating.iter().map(|i| full[*i]).collect()
+    }
+}
+
+impl<O: Objective> Objective for Subspace<'_, O> {
+    fn dimension(&self) -> usize {
+        self.floating.len()
+    }
+
+    fn value(&self, x: &[f64]) -> f64 {
+        self.objective.value(&self.expand(x))
+    }
+
+    fn gradient(&self, x: &[f64], gradient: &mut [f64]) -> f64 {
+        let mut full = vec![0.0; self.values.len()];
+        let value = self.objective.gradient(&self.expand(x), &mut full);
+        gradient.copy_from_slice(&self.restrict(&full));
+        value
+    }
+
+    fn hessian(&self, x: &[f64], gradient: &mut [f64], hessian: &mut Matrix) -> f64 {
+        let mut full_gradient = vec![0.0; self.values.len()];
+        let mut full_hessian = Matrix::zeros(self.values.len());
+        let value = self.objective.hessian(&self.expand(x), &mut full_gradient, &mut full_hessian);
+        gradient.copy_from_slice(&self.restrict(&full_gradient));
+        *hessian = full_hessian.submatrix(&self.floating);
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        likelihood::Nll,
+        minimize::Fitter,
+        parameter::Parameter,
+        toys::{gaussian, gaussian_toy},
+    };
+
+    #[test]
+    fn derivatives_are_those_of_the_floating_entries() {
+        let (model, dataset) = (gaussian(), gaussian_toy(100, 23));
+        let nll = Nll::new(&model, &dataset);
+        let subspace = Subspace { objective: &nll, values: vec![1000.2, 0.7], floating: vec![1] };
+        assert_eq!(subspace.expand(&[0.4]), [1000.2, 0.4]);
+        assert_eq!(subspace.restrict(&[3.0, 0.4]), [0.4]);
+
+        let (mut gradient, mut hessian) = (vec![0.0; 1], Matrix::zeros(1));
+        let (mut full_gradient, mut full_hessian) = (vec![0.0; 2], Matrix::zeros(2));
+        let value = subspace.hessian(&[0.4], &mut gradient, &mut hessian);
+        assert_eq!(value, nll.hessian(&[1000.2, 0.4], &mut full_gradient, &mut full_hessian));
+        assert_eq!((gradient[0], hessian[(0, 0)]), (full_gradient[1], full_hessian[(1, 1)]));
+        assert_eq!(subspace.value(&[0.4]), nll.value(&[1000.2, 0.4]));
+    }
+
+    #[test]
+    fn fixed_parameters_keep_their_value_and_have_no_error() {
+        let dataset = gaussian_toy(1000, 29);
+        let events: Vec<f64> = dataset.events().map(|event| event[0]).collect();
+        let mean = events.iter().sum::<f64>() / events.len() as f64;
+        let fitter = Fitter { tolerance: 1e-12, ..Fitter::default() };
+        let result = fitter.fit(&gaussian(), &dataset, &[Parameter::from(1000.1), Parameter::Fixed(0.8)]);
+
+        assert!(result.converged);
+        assert_eq!((result.parameters[1], result.fixed.clone()), (0.8, vec![false, true]));
+        assert!((result.parameters[0] - mean).abs() <= 1e-9 * mean);
+        // with `sigma` held, the error on the mean is `sigma / sqrt(N)`
+        let expected = 0.8 / (events.len() as f64).sqrt();
+        assert!((result.error("mu").unwrap() - expected).abs() <= 1e-9);
+        let covariance = result.covariance.as_ref().unwrap();
+        assert_eq!((covariance[(0, 1)], covariance[(1, 0)], covariance[(1, 1)]), (0.0, 0.0, 0.0));
+        assert_eq!(result.correlation("mu", "sigma"), Some(0.0));
+    }
+}
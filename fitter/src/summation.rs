@@ -0,0 +1,209 @@
+// how the per-event terms of the nll and its derivatives are added up
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Summation {
+    Naive,
+    // compensated, in Neumaier's variant of Kahan summation
+    Kahan,
+    // pairwise over leaves of `LEAF_SIZE` terms
+    Pairwise,
+}
+
+const LEAF_SIZE: usize = 32;
+
+// running sums of a fixed number of values
+pub(crate) struct Accumulator {
+    summation: Summation,
+    sums: Vec<f64>,
+    compensations: Vec<f64>,
+    // pairwise: the current leaf with its term count, and completed partial sums with the number of leaves each holds,
+    // merged whenever the top two hold the same number
+    count: usize,
+    stack: Vec<(usize, Vec<f64>)>,
+    spare: Vec<Vec<f64>>,
+}
+
+impl Accumulator {
+    pub(crate) fn new(summation: Summation, size: usize) -> Self {
+        Self { summation, sums: vec![0.0; size], compensations: vec![0.0; size], count: 0, stack: Vec::new(), spare: Vec::new() }
+    }
+
+    pub(crate) fn add(&mut self, terms: &[f64]) {
+        match self.summation {
+            Summation::Naive => self.sums.iter_mut().zip(terms).for_each(|(s, t)| *s += t),
+            Summation::Kahan => {
+                for ((sum, compensation), term) in self.sums.iter_mut().zip(&mut self.compensations).zip(terms) {
+                    let total = *sum + term;
+                    *compensation += if sum.abs() >= term.abs() {
+                        (*sum - total) + term
+                    } else {
+                        (term - total) + *sum
+                    };
+                    *sum = total;
+                }
+            }
+            Summation::Pairwise => {
+                self.sums.iter_mut().zip(terms).for_each(|(s, t)| *s += t);
+                self.count += 1;
+                if self.count == LEAF_SIZE {
+                    self.push_leaf();
+                }
+            }
+        }
+    }
+
+    fn push_leaf(&mut self) {
+        let fresh = self.spare.pop().unwrap_or_else(|| vec![0.0; self.sums.len()]);
+        let leaf = std::mem::replace(&mut self.sums, fresh);
+        self.count = 0;
+        let mut entry = (1, leaf);
+        while let Some((leaves, mut below)) = self.stack.pop_if(|(leaves, _)| *leaves == entry.0) {
+            below.iter_mut().zip(&entry.1).for_each(|(b, e)| *b += e);
+            entry.1.fill(0.0);
+            self.spare.push(entry.1);
+            entry = (2 * leaves, below);
+        }
+        self.stack.push(entry);
+    }
+
+    pub(crate) fn total(mut self) -> Vec<f64> {
+        match self.summation {
+            Summation::Naive => self.sums,
+            Summation::Kahan => self.sums.iter().zip(&self.compensations).map(|(s, c)| s + c).collect(),
+            Summation::Pairwise => {
+                // fold the unfinished leaf and the remaining partial sums from the smallest up
+                let mut total = self.sums;
+                while let Some((_, below)) = self.stack.pop() {
+                    total.iter_mut().zip(below).for_each(|(t, b)| *t += b);
+                }
+                total
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        likelihood::{Nll, Objective},
+        linalg::Matrix,
+        toys::{Toy, gaussian, gaussian_toy, grad, hess, lik},
+    };
+
+    const SUMMATIONS: [Summation; 3] = [Summation::Naive, Summation::Kahan, Summation::Pairwise];
+
+    // the correctly rounded sum, keeping the exact value as a list of non-overlapping partials (Shewchuk, 1997)
+    fn exact_sum(terms: impl IntoIterator<Item = f64>) -> f64 {
+        let mut partials: Vec<f64> = Vec::new();
+        for mut x in terms {
+            let mut kept = 0;
+            for i in 0..partials.len() {
+                let mut y = partials[i];
+                if x.abs() < y.abs() {
+                    std::mem::swap(&mut x, &mut y);
+                }
+                let high = x + y;
+                let low = y - (high - x);
+                if low != 0.0 {
+                    partials[kept] = low;
+                    kept += 1;
+                }
+                x = high;
+            }
+            partials.truncate(kept);
+            partials.push(x);
+        }
+        // the partials are increasing in magnitude and do not overlap, so adding from the largest is off by at most an ulp
+        partials.iter().rev().fold(0.0, |total, p| total + p)
+    }
+
+    fn sum(summation: Summation, terms: &[f64]) -> f64 {
+        let mut accumulator = Accumulator::new(summation, 1);
+        terms.iter().for_each(|t| accumulator.add(&[*t]));
+        accumulator.total()[0]
+    }
+
+    fn relative_error(value: f64, reference: f64) -> f64 {
+        ((value - reference) / reference).abs()
+    }
+
+    #[test]
+    fn exact_sum_is_exact() {
+        assert_eq!(exact_sum([1e100, 1.0, -1e100, 1e-100]), 1.0);
+        assert_eq!(exact_sum([0.1; 10]), 1.0);
+        assert_eq!(exact_sum([]), 0.0);
+    }
+
+    #[test]
+    fn every_summation_handles_short_sums() {
+        for summation in SUMMATIONS {
+            assert_eq!(sum(summation, &[]), 0.0);
+            assert_eq!(sum(summation, &[2.5]), 2.5);
+            assert_eq!(sum(summation, &[1.0, 2.0, 3.0]), 6.0);
+        }
+    }
+
+    #[test]
+    fn pairwise_adds_every_term_once() {
+        // integers are summed exactly, so any lost or repeated leaf shows up
+        for n in [1, 31, 32, 33, 64, 95, 1000, 4097] {
+            let terms: Vec<f64> = (1..=n).map(|i| i as f64).collect();
+            assert_eq!(sum(Summation::Pairwise, &terms), (n * (n + 1) / 2) as f64, "{} terms", n);
+        }
+    }
+
+    #[test]
+    fn compensated_sums_recover_cancelled_terms() {
+        let terms = [1e16, 1.0, -1e16, 1.0];
+        assert_eq!(sum(Summation::Kahan, &terms), 2.0);
+        assert_ne!(sum(Summation::Naive, &terms), 2.0);
+    }
+
+    #[test]
+    fn summations_match_the_reference_on_toys() {
+        let mut toy = Toy(0x9e3779b97f4a7c15);
+        for _ in 0..5 {
+            // positive terms spanning several orders of magnitude, like per-event nll values
+            let terms: Vec<f64> = (0..1_000_000).map(|_| (10.0 * toy.uniform()).exp() * (1.0 + toy.normal().abs())).collect();
+            let reference = exact_sum(terms.iter().copied());
+            let naive = relative_error(sum(Summation::Naive, &terms), reference);
+            let kahan = relative_error(sum(Summation::Kahan, &terms), reference);
+            let pairwise = relative_error(sum(Summation::Pairwise, &terms), reference);
+            assert!(kahan <= f64::EPSILON, "kahan error {:e}", kahan);
+            assert!(pairwise <= 16.0 * f64::EPSILON, "pairwise error {:e}", pairwise);
+            assert!(kahan <= naive && pairwise <= naive.max(f64::EPSILON), "naive {:e}, kahan {:e}, pairwise {:e}", naive, kahan, pairwise);
+        }
+    }
+
+    #[test]
+    fn nll_matches_the_reference_on_toys() {
+        let model = gaussian();
+        for seed in [1, 2, 3] {
+            let dataset = gaussian_toy(300_000, seed);
+            let parameters = [999.9, 0.6];
+            let events: Vec<[f64; 1]> = dataset.events().map(|event| [event[0]]).collect();
+            let value = exact_sum(events.iter().map(|event| lik(parameters, *event)));
+            let gradient: Vec<f64> = (0..2).map(|i| exact_sum(events.iter().map(|event| grad(parameters, *event).1[i]))).collect();
+            let packed: Vec<f64> = (0..3).map(|i| exact_sum(events.iter().map(|event| hess(parameters, *event).2[i]))).collect();
+            let reference = Matrix::from_packed(2, &packed);
+
+            for summation in [Summation::Kahan, Summation::Pairwise] {
+                let nll = Nll { summation, ..Nll::new(&model, &dataset) };
+                let mut g = vec![0.0; 2];
+                let mut h = Matrix::zeros(2);
+                let v = nll.hessian(&parameters, &mut g, &mut h);
+                let tolerance = 16.0 * f64::EPSILON;
+                assert!(relative_error(nll.value(&parameters), value) <= tolerance, "{:?} value", summation);
+                assert!(relative_error(v, value) <= tolerance, "{:?} value", summation);
+                for i in 0..2 {
+                    // the gradient cancels near the minimum, so compare against the size of its terms
+                    let scale = exact_sum(events.iter().map(|event| grad(parameters, *event).1[i].abs()));
+                    assert!((g[i] - gradient[i]).abs() <= tolerance * scale, "{:?} gradient {}", summation, i);
+                    for j in 0..2 {
+                        assert!(relative_error(h[(i, j)], reference[(i, j)]) <= tolerance, "{:?} hessian {} {}", summation, i, j);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,47 @@
+// reproducible toy datasets and a hand-written gaussian model for the tests of every module
+use crate::{dataset::Dataset, likelihood::Model};
+
+// xorshift64*, so that the toys are the same on every run
+pub(crate) struct Toy(pub(crate) u64);
+
+impl Toy {
+    pub(crate) fn uniform(&mut self) -> f64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545f4914f6cdd1d) >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub(crate) fn normal(&mut self) -> f64 {
+        (-2.0 * (1.0 - self.uniform()).ln()).sqrt() * (2.0 * std::f64::consts::PI * self.uniform()).cos()
+    }
+}
+
+// the nll of a gaussian in `mu` and `sigma`, without its constant
+pub(crate) fn lik(p: [f64; 2], d: [f64; 1]) -> f64 {
+    let z = (d[0] - p[0]) / p[1];
+    0.5 * z * z + p[1].ln()
+}
+
+pub(crate) fn grad(p: [f64; 2], d: [f64; 1]) -> (f64, [f64; 2]) {
+    let z = (d[0] - p[0]) / p[1];
+    (lik(p, d), [-z / p[1], (1.0 - z * z) / p[1]])
+}
+
+pub(crate) fn hess(p: [f64; 2], d: [f64; 1]) -> (f64, [f64; 2], [f64; 3]) {
+    let (value, gradient) = grad(p, d);
+    let (z, s2) = ((d[0] - p[0]) / p[1], p[1] * p[1]);
+    (value, gradient, [1.0 / s2, 2.0 * z / s2, (3.0 * z * z - 1.0) / s2])
+}
+
+pub(crate) fn gaussian() -> Model<2, 1, 3> {
+    Model { names: ["mu", "sigma"], likelihood: lik, gradient: grad, hessian: hess, extended: None }
+}
+
+// `events` draws from a gaussian with mean 1000 and width 0.5
+pub(crate) fn gaussian_toy(events: usize, seed: u64) -> Dataset {
+    let mut toy = Toy(seed);
+    let mut dataset = Dataset::new(1);
+    (0..events).for_each(|_| dataset.push(&[1000.0 + 0.5 * toy.normal()]));
+    dataset
+}
@@ -0,0 +1,104 @@
+use crate::{likelihood::Objective, linalg::Matrix, parameter::Bounds};
+
+// objective over the unbounded internal parameters of a bounded objective
+pub struct Transformed<'a, O: Objective> {
+    pub objective: &'a O,
+    pub bounds: &'a [Bounds],
+}
+
+impl<O: Objective> Transformed<'_, O> {
+    pub fn to_external(&self, internal: &[f64]) -> Vec<f64> {
+        internal.iter().zip(self.bounds).map(|(u, b)| b.to_external(*u)).collect()
+    }
+
+    pub fn to_internal(&self, external: &[f64]) -> Vec<f64> {
+        external.iter().zip(self.bounds).map(|(x, b)| b.to_internal(*x)).collect()
+    }
+}
+
+impl<O: Objective> Objective for Transformed<'_, O> {
+    fn dimension(&self) -> usize {
+        self.objective.dimension()
+    }
+
+    fn value(&self, x: &[f64]) -> f64 {
+        self.objective.value(&self.to_external(x))
+    }
+
+    fn gradient(&self, x: &[f64], gradient: &mut [f64]) -> f64 {
+        let value = self.objective.gradient(&self.to_external(x), gradient);
+        for i in 0..x.len() {
+            gradient[i] *= self.bounds[i].derivatives(x[i]).0;
+        }
+        value
+    }
+
+    // d2f/du_i du_j = H_ij x'_i x'_j + delta_ij g_i x''_i
+    fn hessian(&self, x: &[f64], gradient: &mut [f64], hessian: &mut Matrix) -> f64 {
+        let value = self.objective.hessian(&self.to_external(x), gradient, hessian);
+        let derivatives: Vec<(f64, f64)> = x.iter().zip(self.bounds).map(|(u, b)| b.derivatives(*u)).collect();
+        for i in 0..x.len() {
+            for j in 0..x.len() {
+                hessian[(i, j)] *= derivatives[i].0 * derivatives[j].0;
+            }
+            hessian[(i, i)] += gradient[i] * derivatives[i].1;
+        }
+        for i in 0..x.len() {
+            gradient[i] *= derivatives[i].0;
+        }
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        likelihood::Nll,
+        minimize::Fitter,
+        parameter::Parameter,
+        toys::{gaussian, gaussian_toy},
+    };
+
+    #[test]
+    fn derivatives_follow_the_chain_rule() {
+        let (model, dataset) = (gaussian(), gaussian_toy(100, 17));
+        let nll = Nll::new(&model, &dataset);
+        let bounds = [Bounds::Both(999.0, 1001.0), Bounds::Lower(0.1)];
+        let transformed = Transformed { objective: &nll, bounds: &bounds };
+        let x = [0.3, 0.8];
+        let (mut gradient, mut hessian) = (vec![0.0; 2], Matrix::zeros(2));
+        transformed.hessian(&x, &mut gradient, &mut hessian);
+
+        let step = 1e-6;
+        for i in 0..2 {
+            let (mut up, mut down) = (x, x);
+            up[i] += step;
+            down[i] -= step;
+            let difference = (transformed.value(&up) - transformed.value(&down)) / (2.0 * step);
+            assert!((gradient[i] - difference).abs() <= 1e-5 * difference.abs().max(1.0), "gradient {}", i);
+            let (mut up_gradient, mut down_gradient) = (vec![0.0; 2], vec![0.0; 2]);
+            transformed.gradient(&up, &mut up_gradient);
+            transformed.gradient(&down, &mut down_gradient);
+            for j in 0..2 {
+                let difference = (up_gradient[j] - down_gradient[j]) / (2.0 * step);
+                assert!((hessian[(i, j)] - difference).abs() <= 1e-5 * difference.abs().max(1.0), "hessian {} {}", i, j);
+            }
+        }
+    }
+
+    #[test]
+    fn bounded_fits_stop_at_the_boundary() {
+        let dataset = gaussian_toy(1000, 19);
+        let fitter = Fitter { tolerance: 1e-12, ..Fitter::default() };
+        let free = fitter.fit(&gaussian(), &dataset, &[Parameter::from(1000.1), Parameter::from(0.6)]);
+
+        // a bound the estimate lies inside changes nothing, and one it lies beyond holds the parameter at the bound
+        let inside = fitter.fit(&gaussian(), &dataset, &[Parameter::from(1000.1), Parameter::floating(0.6, Bounds::Lower(0.01))]);
+        assert!((inside.parameters[1] - free.parameters[1]).abs() <= 1e-6 * free.parameters[1]);
+        let ceiling = free.parameters[0] - 0.1;
+        let result = fitter.fit(&gaussian(), &dataset, &[Parameter::floating(ceiling - 1.0, Bounds::Upper(ceiling)), Parameter::from(0.6)]);
+        assert!(result.converged);
+        assert!(ceiling - result.parameters[0] <= 1e-6, "{} against {}", result.parameters[0], ceiling);
+    }
+}
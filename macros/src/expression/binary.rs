@@ -1,9 +1,8 @@
-use std::rc::Rc;
-
 use proc_macro2::TokenStream;
 use quote::quote;
 use syn::Ident;
 
+use crate::expression::select::{Comparison, Select};
 use crate::expression::variable::Variable;
 use crate::expression::{Graph, Node, NodeType};
 use crate::expression::{Unary, UnaryOp};
@@ -15,6 +14,15 @@ pub(crate) enum BinaryOp {
     Mul,
     Div,
     Pow,
+    // integer powers, with a constant integer exponent
+    Powi,
+    Atan2,
+    Hypot,
+    // real and imaginary parts of the faddeeva function `w(x + i y)`, evaluated by `fastfit::special`
+    FaddeevaRe,
+    FaddeevaIm,
+    Max,
+    Min,
 }
 
 #[derive(Debug, Eq, Hash, PartialEq)]
@@ -25,9 +33,15 @@ pub(crate) struct Binary {
 }
 
 impl Binary {
+    #[allow(clippy::new_ret_no_self)]
     pub(crate) fn new(graph: &mut Graph, operation: BinaryOp, left: *const Node, right: *const Node) -> *const Node {
         let leftref = unsafe { &*left };
         let rightref = unsafe { &*right };
+        if let (NodeType::Constant(l), NodeType::Constant(r)) = (&leftref.interior, &rightref.interior)
+            && let Some(value) = operation.evaluate(l.value, r.value)
+        {
+            return graph.new_constant(value);
+        }
         match operation {
             BinaryOp::Add => {
                 if *leftref == 0.0 {
@@ -77,9 +91,62 @@ impl Binary {
                 } else if *rightref == 1.0 {
                     return left;
                 } else if *rightref == 2.0 {
-                    return Self::new(graph, BinaryOp::Mul, left.clone(), left.clone());
+                    return Self::new(graph, BinaryOp::Mul, left, left);
+                }
+            }
+            BinaryOp::Powi => {
+                if *rightref == 0.0 {
+                    return graph.new_constant(1.0);
+                } else if *rightref == 1.0 {
+                    return left;
+                } else if *rightref == 2.0 {
+                    return Self::new(graph, BinaryOp::Mul, left, left);
+                } else if *rightref == -1.0 {
+                    let one = graph.new_constant(1.0);
+                    return Self::new(graph, BinaryOp::Div, one, left);
                 }
             }
+            BinaryOp::Hypot => {
+                if *leftref == 0.0 {
+                    return Unary::new(graph, UnaryOp::Abs, right);
+                } else if *rightref == 0.0 {
+                    return Unary::new(graph, UnaryOp::Abs, left);
+                }
+            }
+            BinaryOp::Max | BinaryOp::Min => {
+                if left == right {
+                    return left;
+                }
+                // constants go on the right
+                if matches!(leftref.interior, NodeType::Constant(_)) && !matches!(rightref.interior, NodeType::Constant(_)) {
+                    return Self::new(graph, operation, right, left);
+                }
+                if let NodeType::Constant(c) = &rightref.interior {
+                    let (bound, other) = match operation {
+                        BinaryOp::Max => (f64::NEG_INFINITY, BinaryOp::Min),
+                        _ => (f64::INFINITY, BinaryOp::Max),
+                    };
+                    if c.value == bound {
+                        return left;
+                    }
+                    if let NodeType::Binary(b) = &leftref.interior
+                        && let NodeType::Constant(inner) = &unsafe { &*b.right }.interior
+                    {
+                        // max(max(x, a), b) = max(x, max(a, b))
+                        if b.operation == operation
+                            && let Some(value) = operation.evaluate(inner.value, c.value)
+                        {
+                            let combined = graph.new_constant(value);
+                            return Self::new(graph, operation, b.left, combined);
+                        }
+                        // max(min(x, a), b) = b where a <= b
+                        if b.operation == other && operation.evaluate(inner.value, c.value) == Some(c.value) {
+                            return right;
+                        }
+                    }
+                }
+            }
+            BinaryOp::Atan2 | BinaryOp::FaddeevaRe | BinaryOp::FaddeevaIm => {}
         }
         let binary = Self { operation, left, right };
         graph.insert(Node::new(NodeType::Binary(binary)))
@@ -93,49 +160,158 @@ impl Binary {
             BinaryOp::Add => Self::new(graph, BinaryOp::Add, left_deriv, right_deriv),
             BinaryOp::Sub => Self::new(graph, BinaryOp::Sub, left_deriv, right_deriv),
             BinaryOp::Mul => {
-                let left = Self::new(graph, BinaryOp::Mul, left_deriv, self.right.clone());
-                let right = Self::new(graph, BinaryOp::Mul, self.left.clone(), right_deriv);
+                let left = Self::new(graph, BinaryOp::Mul, left_deriv, self.right);
+                let right = Self::new(graph, BinaryOp::Mul, self.left, right_deriv);
                 Self::new(graph, BinaryOp::Add, left, right)
             }
             BinaryOp::Div => {
-                let left = Self::new(graph, BinaryOp::Mul, left_deriv, self.right.clone());
-                let right = Self::new(graph, BinaryOp::Mul, self.left.clone(), right_deriv);
+                let left = Self::new(graph, BinaryOp::Mul, left_deriv, self.right);
+                let right = Self::new(graph, BinaryOp::Mul, self.left, right_deriv);
                 let numerator = Self::new(graph, BinaryOp::Sub, left, right);
-                let denominator = Self::new(graph, BinaryOp::Mul, self.right.clone(), self.right.clone());
+                let denominator = Self::new(graph, BinaryOp::Mul, self.right, self.right);
                 Self::new(graph, BinaryOp::Div, numerator, denominator)
             }
             BinaryOp::Pow => {
                 if let NodeType::Constant(c) = &unsafe { &*self.right }.interior {
                     let new_exp = graph.new_constant(c.value - 1.0);
-                    let new = Self::new(graph, BinaryOp::Pow, self.left.clone(), new_exp);
-                    let deriv = Self::new(graph, BinaryOp::Mul, self.right.clone(), new);
+                    let new = Self::new(graph, BinaryOp::Pow, self.left, new_exp);
+                    let deriv = Self::new(graph, BinaryOp::Mul, self.right, new);
                     Self::new(graph, BinaryOp::Mul, left_deriv, deriv)
                 } else {
-                    panic!("non-constant exponents are not yet supported");
+                    // d a^b = a^b (db ln a + b da / a)
+                    let power = Self::new(graph, BinaryOp::Pow, self.left, self.right);
+                    let log = Unary::new(graph, UnaryOp::Log, self.left);
+                    let exponent_term = Self::new(graph, BinaryOp::Mul, right_deriv, log);
+                    let ratio = Self::new(graph, BinaryOp::Div, left_deriv, self.left);
+                    let base_term = Self::new(graph, BinaryOp::Mul, self.right, ratio);
+                    let sum = Self::new(graph, BinaryOp::Add, exponent_term, base_term);
+                    Self::new(graph, BinaryOp::Mul, power, sum)
                 }
             }
+            BinaryOp::Powi => {
+                let NodeType::Constant(c) = &unsafe { &*self.right }.interior else {
+                    unreachable!("`powi` exponents are checked to be constant when the graph is built");
+                };
+                let new_exp = graph.new_constant(c.value - 1.0);
+                let new = Self::new(graph, BinaryOp::Powi, self.left, new_exp);
+                let deriv = Self::new(graph, BinaryOp::Mul, self.right, new);
+                Self::new(graph, BinaryOp::Mul, left_deriv, deriv)
+            }
+            // d atan2(y, x) = (x dy - y dx) / (x^2 + y^2)
+            BinaryOp::Atan2 => {
+                let left = Self::new(graph, BinaryOp::Mul, self.right, left_deriv);
+                let right = Self::new(graph, BinaryOp::Mul, self.left, right_deriv);
+                let numerator = Self::new(graph, BinaryOp::Sub, left, right);
+                let y2 = Self::new(graph, BinaryOp::Mul, self.left, self.left);
+                let x2 = Self::new(graph, BinaryOp::Mul, self.right, self.right);
+                let denominator = Self::new(graph, BinaryOp::Add, y2, x2);
+                Self::new(graph, BinaryOp::Div, numerator, denominator)
+            }
+            // d hypot(x, y) = (x dx + y dy) / hypot(x, y)
+            BinaryOp::Hypot => {
+                let left = Self::new(graph, BinaryOp::Mul, self.left, left_deriv);
+                let right = Self::new(graph, BinaryOp::Mul, self.right, right_deriv);
+                let numerator = Self::new(graph, BinaryOp::Add, left, right);
+                let hypot = Self::new(graph, BinaryOp::Hypot, self.left, self.right);
+                Self::new(graph, BinaryOp::Div, numerator, hypot)
+            }
+            // w'(z) = 2i / sqrt(pi) - 2 z w(z), with dw/dx = w' and dw/dy = i w'
+            BinaryOp::FaddeevaRe | BinaryOp::FaddeevaIm => {
+                let (x, y) = (self.left, self.right);
+                let u = Self::new(graph, BinaryOp::FaddeevaRe, x, y);
+                let v = Self::new(graph, BinaryOp::FaddeevaIm, x, y);
+                let two = graph.new_constant(2.0);
+                let xu = Self::new(graph, BinaryOp::Mul, x, u);
+                let yv = Self::new(graph, BinaryOp::Mul, y, v);
+                let real = Self::new(graph, BinaryOp::Sub, xu, yv);
+                let real = Self::new(graph, BinaryOp::Mul, two, real);
+                let real = Unary::new(graph, UnaryOp::Negative, real);
+                let xv = Self::new(graph, BinaryOp::Mul, x, v);
+                let yu = Self::new(graph, BinaryOp::Mul, y, u);
+                let imaginary = Self::new(graph, BinaryOp::Add, xv, yu);
+                let imaginary = Self::new(graph, BinaryOp::Mul, two, imaginary);
+                let constant = graph.new_constant(std::f64::consts::FRAC_2_SQRT_PI);
+                let imaginary = Self::new(graph, BinaryOp::Sub, constant, imaginary);
+
+                let (along_x, along_y) = if self.operation == BinaryOp::FaddeevaRe {
+                    let negative = Unary::new(graph, UnaryOp::Negative, imaginary);
+                    (real, negative)
+                } else {
+                    (imaginary, real)
+                };
+                let left = Self::new(graph, BinaryOp::Mul, along_x, left_deriv);
+                let right = Self::new(graph, BinaryOp::Mul, along_y, right_deriv);
+                Self::new(graph, BinaryOp::Add, left, right)
+            }
+            // the derivative of the side equal to the result, taking the left one at ties; a nan side never equals it, as in `f64::max`
+            BinaryOp::Max | BinaryOp::Min => {
+                let result = Self::new(graph, self.operation.clone(), self.left, self.right);
+                Select::new(graph, Comparison::Equal, self.left, result, left_deriv, right_deriv)
+            }
         }
     }
 }
 
 impl BinaryOp {
+    // the operation behind a method of that name on `Float` taking one argument, or a function taking two
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "powf" => Some(Self::Pow),
+            "powi" => Some(Self::Powi),
+            "atan2" => Some(Self::Atan2),
+            "hypot" => Some(Self::Hypot),
+            "faddeeva_re" => Some(Self::FaddeevaRe),
+            "faddeeva_im" => Some(Self::FaddeevaIm),
+            "max" => Some(Self::Max),
+            "min" => Some(Self::Min),
+            _ => None,
+        }
+    }
+
+    // folds constant operands of the new operations, unless the result is not finite
+    fn evaluate(&self, left: f64, right: f64) -> Option<f64> {
+        let value = match &self {
+            Self::Powi => left.powi(right as i32),
+            Self::Atan2 => left.atan2(right),
+            Self::Hypot => left.hypot(right),
+            Self::Max => left.max(right),
+            Self::Min => left.min(right),
+            _ => return None,
+        };
+        value.is_finite().then_some(value)
+    }
+
     pub fn cost(&self) -> usize {
         match &self {
             Self::Add => 3,
             Self::Sub => 3,
             Self::Mul => 5,
             Self::Div => 20,
+            Self::Powi => 20,
+            Self::Hypot => 30,
+            Self::Max => 3,
+            Self::Min => 3,
             Self::Pow => 100,
+            Self::Atan2 => 100,
+            Self::FaddeevaRe => 400,
+            Self::FaddeevaIm => 400,
         }
     }
 
-    pub fn generate_rust(&self, result: Ident, left_value: Ident, right_value: Ident) -> TokenStream {
+    pub fn generate_rust(&self, left_value: Ident, right_value: Ident) -> TokenStream {
         match &self {
-            Self::Add => quote! { let #result = #left_value + #right_value; },
-            Self::Sub => quote! { let #result = #left_value - #right_value; },
-            Self::Mul => quote! { let #result = #left_value * #right_value; },
-            Self::Div => quote! { let #result = #left_value / #right_value; },
-            Self::Pow => quote! { let #result = #left_value.powf(#right_value as f64); },
+            Self::Add => quote! { #left_value + #right_value },
+            Self::Sub => quote! { #left_value - #right_value },
+            Self::Mul => quote! { #left_value * #right_value },
+            Self::Div => quote! { #left_value / #right_value },
+            Self::Pow => quote! { #left_value.powf(#right_value as f64) },
+            Self::Powi => quote! { #left_value.powi(#right_value as i32) },
+            Self::Atan2 => quote! { #left_value.atan2(#right_value) },
+            Self::Hypot => quote! { #left_value.hypot(#right_value) },
+            Self::FaddeevaRe => quote! { fastfit::special::faddeeva_re(#left_value, #right_value) },
+            Self::FaddeevaIm => quote! { fastfit::special::faddeeva_im(#left_value, #right_value) },
+            Self::Max => quote! { #left_value.max(#right_value) },
+            Self::Min => quote! { #left_value.min(#right_value) },
         }
     }
 }
@@ -1,6 +1,7 @@
 pub(crate) mod binary;
 pub(crate) mod collection;
 pub(crate) mod constant;
+pub(crate) mod select;
 pub(crate) mod unary;
 pub(crate) mod variable;
 
@@ -9,6 +10,7 @@ use std::collections::{HashMap, HashSet};
 use binary::Binary;
 use collection::Collection;
 use constant::Constant;
+use select::{Comparison, Select};
 use unary::Unary;
 use variable::Variable;
 
@@ -23,6 +25,7 @@ pub(crate) enum NodeType {
     Unary(Unary),
     Variable(Variable),
     Collection(Collection),
+    Select(Select),
 }
 
 #[derive(Debug, Eq, Hash, PartialEq)]
@@ -57,20 +60,28 @@ impl Node {
                     let cost = 1;
                     Self { interior, parameters, data, cost }
                 }
+                NodeType::Select(s) => {
+                    let operands = [s.left, s.right, s.then, s.otherwise];
+                    let parameters = operands.iter().any(|operand| (**operand).parameters);
+                    let data = operands.iter().any(|operand| (**operand).data);
+                    let cost = operands.iter().map(|operand| (**operand).cost).sum::<usize>() + s.comparison.cost();
+                    Self { interior, parameters, data, cost }
+                }
             }
         }
     }
-    fn get_children(&self) -> Vec<*const Node> {
+    pub(crate) fn get_children(&self) -> Vec<*const Node> {
         match &self.interior {
             NodeType::Constant(_) => vec![],
             NodeType::Collection(c) => match c {
                 Collection::Array(a) => a.elements.clone(),
-                Collection::Struct(s) => s.elements.values().map(|v| v.clone()).collect(),
+                Collection::Struct(s) => s.elements.values().copied().collect(),
                 Collection::Tuple(a) => a.elements.clone(),
             },
             NodeType::Variable(_) => vec![],
-            NodeType::Unary(u) => vec![u.argument.clone()],
-            NodeType::Binary(b) => vec![b.left.clone(), b.right.clone()],
+            NodeType::Unary(u) => vec![u.argument],
+            NodeType::Binary(b) => vec![b.left, b.right],
+            NodeType::Select(s) => vec![s.left, s.right, s.then, s.otherwise],
         }
     }
 }
@@ -96,11 +107,16 @@ pub struct Function {
 pub struct Graph {
     vertices: HashSet<Box<Node>>,
     functions: HashMap<String, Function>,
+    derivatives: HashMap<(*const Node, Variable), *const Node>,
+    pub(crate) value: Option<*const Node>,
+    pub(crate) gradient: Vec<*const Node>,
+    // upper triangle, row by row
+    pub(crate) hessian: Vec<*const Node>,
 }
 
 impl Graph {
     pub fn new() -> Self {
-        Self { vertices: HashSet::new(), functions: HashMap::new() }
+        Self { vertices: HashSet::new(), functions: HashMap::new(), derivatives: HashMap::new(), value: None, gradient: Vec::new(), hessian: Vec::new() }
     }
 
     pub(crate) fn new_collection(&mut self, collection: Collection) -> *const Node {
@@ -111,8 +127,8 @@ impl Graph {
         self.insert(Node::new(NodeType::Constant(Constant { value })))
     }
 
-    pub(crate) fn new_variable(&mut self, name: String, parameter: bool) -> *const Node {
-        let var = Node::new(NodeType::Variable(Variable { name: name.clone(), parameter, index: 0 }));
+    pub(crate) fn new_variable(&mut self, name: String, parameter: bool, index: usize) -> *const Node {
+        let var = Node::new(NodeType::Variable(Variable { name: name.clone(), parameter, index }));
         self.insert(var)
     }
 
@@ -124,6 +140,10 @@ impl Graph {
         Binary::new(self, operand, left, right)
     }
 
+    pub(crate) fn new_select(&mut self, comparison: Comparison, left: *const Node, right: *const Node, then: *const Node, otherwise: *const Node) -> *const Node {
+        Select::new(self, comparison, left, right, then, otherwise)
+    }
+
     pub fn insert(&mut self, node: Node) -> *const Node {
         let b = Box::new(node);
         if let Some(existing) = self.vertices.get(&b) {
@@ -140,36 +160,40 @@ impl Graph {
         let value = function.result;
 
         let mut map = HashMap::new();
-        for i in 0..inputs.len() {
-            map.insert(function.arguments[i].clone(), inputs[i].clone());
+        for (argument, input) in function.arguments.iter().zip(&inputs) {
+            map.insert(*argument, *input);
         }
-        let order = function.order(&self);
+        let order = function.order();
 
         for node in order {
             let spliced_node = match &unsafe { &*node }.interior {
-                NodeType::Binary(b) => self.new_binary(b.operation.clone(), map.get(&b.left).unwrap().clone(), map.get(&b.right).unwrap().clone()),
+                NodeType::Binary(b) => self.new_binary(b.operation.clone(), *map.get(&b.left).unwrap(), *map.get(&b.right).unwrap()),
                 NodeType::Collection(c) => match c {
                     Collection::Array(a) => {
-                        let elements = a.elements.iter().map(|e| map.get(e).unwrap().clone()).collect();
+                        let elements = a.elements.iter().map(|e| *map.get(e).unwrap()).collect();
                         self.new_collection(Collection::Array(Array { elements }))
                     }
                     Collection::Struct(s) => {
                         let name_order = s.name_order.clone();
                         let mut elements = HashMap::new();
                         name_order.iter().for_each(|name| {
-                            let element = map.get(s.elements.get(name).unwrap()).unwrap().clone();
+                            let element = *map.get(s.elements.get(name).unwrap()).unwrap();
                             elements.insert(name.clone(), element);
                         });
                         self.new_collection(Collection::Struct(Struct { name: s.name.clone(), name_order, elements }))
                     }
                     Collection::Tuple(t) => {
-                        let elements = t.elements.iter().map(|e| map.get(e).unwrap().clone()).collect();
+                        let elements = t.elements.iter().map(|e| *map.get(e).unwrap()).collect();
                         self.new_collection(Collection::Tuple(Tuple { elements }))
                     }
                 },
                 NodeType::Constant(c) => self.new_constant(c.value),
-                NodeType::Unary(u) => self.new_unary(u.operation.clone(), map.get(&u.argument).unwrap().clone()),
-                NodeType::Variable(_) => map.get(&node).unwrap().clone(),
+                NodeType::Unary(u) => self.new_unary(u.operation.clone(), *map.get(&u.argument).unwrap()),
+                NodeType::Variable(_) => *map.get(&node).unwrap(),
+                NodeType::Select(s) => {
+                    let [left, right, then, otherwise] = [s.left, s.right, s.then, s.otherwise].map(|operand| *map.get(&operand).unwrap());
+                    self.new_select(s.comparison, left, right, then, otherwise)
+                }
             };
             map.insert(node, spliced_node);
         }
@@ -220,19 +244,116 @@ impl Graph {
     //     map.get(value).unwrap().clone()
     // }
 
+    // copies `node` of another graph into this one, replacing parameter variables by the nodes in `parameters`, keyed by name
+    pub(crate) fn substitute(&mut self, node: *const Node, parameters: &HashMap<String, *const Node>) -> *const Node {
+        let mut map = HashMap::new();
+        self.substitute_into(node, parameters, &mut map)
+    }
+
+    fn substitute_into(&mut self, node: *const Node, parameters: &HashMap<String, *const Node>, map: &mut HashMap<*const Node, *const Node>) -> *const Node {
+        if let Some(substituted) = map.get(&node) {
+            return *substituted;
+        }
+        let substituted = match &unsafe { &*node }.interior {
+            NodeType::Binary(b) => {
+                let left = self.substitute_into(b.left, parameters, map);
+                let right = self.substitute_into(b.right, parameters, map);
+                self.new_binary(b.operation.clone(), left, right)
+            }
+            NodeType::Collection(_) => panic!("attempted to substitute into a collection"),
+            NodeType::Constant(c) => self.new_constant(c.value),
+            NodeType::Unary(u) => {
+                let argument = self.substitute_into(u.argument, parameters, map);
+                self.new_unary(u.operation.clone(), argument)
+            }
+            NodeType::Variable(v) if v.parameter => *parameters.get(&v.name).unwrap(),
+            NodeType::Variable(v) => self.new_variable(v.name.clone(), false, v.index),
+            NodeType::Select(s) => {
+                let [left, right, then, otherwise] = [s.left, s.right, s.then, s.otherwise].map(|operand| self.substitute_into(operand, parameters, map));
+                self.new_select(s.comparison, left, right, then, otherwise)
+            }
+        };
+        map.insert(node, substituted);
+        substituted
+    }
+
+    // names of the data variables that `node` depends on
+    pub(crate) fn data_variables(&self, node: *const Node) -> HashSet<String> {
+        let mut visited = HashSet::new();
+        let mut names = HashSet::new();
+        let mut stack = vec![node];
+        while let Some(node) = stack.pop() {
+            if !visited.insert(node) {
+                continue;
+            }
+            let node = unsafe { &*node };
+            if let NodeType::Variable(v) = &node.interior
+                && !v.parameter
+            {
+                names.insert(v.name.clone());
+            }
+            stack.extend(node.get_children());
+        }
+        names
+    }
+
+    // derivatives are cached, since shared subexpressions would otherwise be differentiated once per use
     pub fn differentiate(&mut self, node: *const Node, variable: &Variable) -> *const Node {
-        match &unsafe { &*node }.interior {
+        let key = (node, variable.clone());
+        if let Some(derivative) = self.derivatives.get(&key) {
+            return *derivative;
+        }
+        let derivative = match &unsafe { &*node }.interior {
             NodeType::Binary(b) => b.differentiate(self, variable),
             NodeType::Collection(_) => panic!("attempted to differentiate a collection"),
             NodeType::Constant(_) => self.new_constant(0.0),
             NodeType::Unary(u) => u.differentiate(self, variable),
             NodeType::Variable(v) => self.new_constant((v == variable) as u64 as f64),
+            NodeType::Select(s) => s.differentiate(self, variable),
+        };
+        self.derivatives.insert(key, derivative);
+        derivative
+    }
+
+    // derivatives of `value` with respect to the parameters named in `parameters`, in their order
+    pub(crate) fn compute_gradient(&mut self, parameters: &[String]) {
+        let value = self.value.unwrap();
+        self.gradient = parameters.iter().map(|name| self.differentiate(value, &Variable::new(name.clone(), true))).collect();
+    }
+
+    pub(crate) fn compute_hessian(&mut self, parameters: &[String]) {
+        let mut hessian = Vec::new();
+        for i in 0..parameters.len() {
+            for name in &parameters[i..] {
+                hessian.push(self.differentiate(self.gradient[i], &Variable::new(name.clone(), true)));
+            }
         }
+        self.hessian = hessian;
+    }
+
+    // `roots` and the nodes they depend on, each after its children
+    pub(crate) fn order(&self, roots: &[*const Node]) -> Vec<*const Node> {
+        fn dfs(node: *const Node, visited: &mut HashSet<*const Node>, sorted: &mut Vec<*const Node>) {
+            if !visited.insert(node) {
+                return;
+            }
+            for child in unsafe { &*node }.get_children() {
+                dfs(child, visited, sorted);
+            }
+            sorted.push(node);
+        }
+
+        let mut visited = HashSet::new();
+        let mut sorted = Vec::new();
+        for root in roots {
+            dfs(*root, &mut visited, &mut sorted);
+        }
+        sorted
     }
 }
 
 impl Function {
-    fn validate_inputs(&self, inputs: &Vec<*const Node>) {
+    fn validate_inputs(&self, inputs: &[*const Node]) {
         if inputs.len() != self.arguments.len() {
             panic!("function `{}` takes `{}` arguments, but was given `{}`", self.name, self.arguments.len(), inputs.len());
         }
@@ -257,8 +378,8 @@ impl Function {
 
         for i in 0..variables.len() {
             let gradient_i = graph.differentiate(value, variables[i]);
-            for j in i..variables.len() {
-                hessian.push(graph.differentiate(gradient_i, variables[j]));
+            for variable in &variables[i..] {
+                hessian.push(graph.differentiate(gradient_i, variable));
             }
             gradient.push(gradient_i);
         }
@@ -276,21 +397,21 @@ impl Function {
         graph.functions.insert(hessian_name, gradient_hessian_function);
     }
 
-    pub fn order(&self, graph: &Graph) -> Vec<*const Node> {
+    pub fn order(&self) -> Vec<*const Node> {
         let mut visited = HashSet::new();
         let mut sorted = Vec::new();
 
-        fn dfs(graph: &Graph, node: &*const Node, visited: &mut HashSet<*const Node>, sorted: &mut Vec<*const Node>) {
+        fn dfs(node: &*const Node, visited: &mut HashSet<*const Node>, sorted: &mut Vec<*const Node>) {
             if visited.contains(node) {
                 return;
             }
-            visited.insert(node.clone());
+            visited.insert(*node);
             for child in &unsafe { &**node }.get_children() {
-                dfs(graph, child, visited, sorted);
+                dfs(child, visited, sorted);
             }
-            sorted.push(node.clone());
+            sorted.push(*node);
         }
-        dfs(graph, &self.result, &mut visited, &mut sorted);
+        dfs(&self.result, &mut visited, &mut sorted);
         sorted
     }
 }
@@ -0,0 +1,93 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::Ident;
+
+use crate::expression::variable::Variable;
+use crate::expression::{Graph, Node, NodeType};
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum Comparison {
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+}
+
+// `then` where `left` compares to `right`, `otherwise` elsewhere (including when either side is nan)
+#[derive(Debug, Eq, Hash, PartialEq)]
+pub(crate) struct Select {
+    pub(crate) comparison: Comparison,
+    pub(crate) left: *const Node,
+    pub(crate) right: *const Node,
+    pub(crate) then: *const Node,
+    pub(crate) otherwise: *const Node,
+}
+
+impl Select {
+    #[allow(clippy::new_ret_no_self)]
+    pub(crate) fn new(graph: &mut Graph, comparison: Comparison, left: *const Node, right: *const Node, then: *const Node, otherwise: *const Node) -> *const Node {
+        if then == otherwise {
+            return then;
+        }
+        let leftref = unsafe { &*left };
+        let rightref = unsafe { &*right };
+        if let (NodeType::Constant(l), NodeType::Constant(r)) = (&leftref.interior, &rightref.interior) {
+            return if comparison.evaluate(l.value, r.value) {
+                then
+            } else {
+                otherwise
+            };
+        }
+        // x < x is false even for nan, but x <= x is not always true, so only the strict comparisons fold
+        if left == right && matches!(comparison, Comparison::Less | Comparison::Greater) {
+            return otherwise;
+        }
+        let select = Self { comparison, left, right, then, otherwise };
+        graph.insert(Node::new(NodeType::Select(select)))
+    }
+
+    // branch-wise, so the derivative jumps where the value does and is one-sided at the threshold
+    pub(crate) fn differentiate(&self, graph: &mut Graph, variable: &Variable) -> *const Node {
+        let then = graph.differentiate(self.then, variable);
+        let otherwise = graph.differentiate(self.otherwise, variable);
+        Self::new(graph, self.comparison, self.left, self.right, then, otherwise)
+    }
+}
+
+impl Comparison {
+    pub(crate) fn from_operator(operator: &syn::BinOp) -> Option<Self> {
+        match operator {
+            syn::BinOp::Lt(_) => Some(Self::Less),
+            syn::BinOp::Le(_) => Some(Self::LessEqual),
+            syn::BinOp::Gt(_) => Some(Self::Greater),
+            syn::BinOp::Ge(_) => Some(Self::GreaterEqual),
+            syn::BinOp::Eq(_) => Some(Self::Equal),
+            _ => None,
+        }
+    }
+
+    fn evaluate(&self, left: f64, right: f64) -> bool {
+        match &self {
+            Self::Less => left < right,
+            Self::LessEqual => left <= right,
+            Self::Greater => left > right,
+            Self::GreaterEqual => left >= right,
+            Self::Equal => left == right,
+        }
+    }
+
+    pub fn cost(&self) -> usize {
+        3
+    }
+
+    pub fn generate_rust(&self, left_value: Ident, right_value: Ident) -> TokenStream {
+        match &self {
+            Self::Less => quote! { #left_value < #right_value },
+            Self::LessEqual => quote! { #left_value <= #right_value },
+            Self::Greater => quote! { #left_value > #right_value },
+            Self::GreaterEqual => quote! { #left_value >= #right_value },
+            Self::Equal => quote! { #left_value == #right_value },
+        }
+    }
+}
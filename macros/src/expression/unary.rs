@@ -1,5 +1,3 @@
-use std::rc::Rc;
-
 use proc_macro2::TokenStream;
 use quote::quote;
 use syn::Ident;
@@ -17,6 +15,24 @@ pub(crate) enum UnaryOp {
     Sin,
     Cos,
     Tan,
+    Sqrt,
+    Abs,
+    Signum,
+    Sinh,
+    Cosh,
+    Tanh,
+    Asin,
+    Acos,
+    Atan,
+    Log10,
+    Log2,
+    Exp2,
+    // special functions, evaluated by `fastfit::special`
+    Erf,
+    Erfc,
+    LnGamma,
+    Digamma,
+    Trigamma,
 }
 
 #[derive(Debug, Eq, Hash, PartialEq)]
@@ -26,38 +42,86 @@ pub(crate) struct Unary {
 }
 
 impl Unary {
+    #[allow(clippy::new_ret_no_self)]
     pub(crate) fn new(graph: &mut Graph, operation: UnaryOp, argument: *const Node) -> *const Node {
         let argref = &unsafe { &*argument }.interior;
 
+        if let NodeType::Constant(c) = argref
+            && let Some(value) = operation.evaluate(c.value)
+        {
+            return graph.new_constant(value);
+        }
+        // the negation of an odd function's argument moves outside, and disappears for even ones
+        if let NodeType::Unary(u) = argref
+            && u.operation == UnaryOp::Negative
+        {
+            match operation {
+                UnaryOp::Abs | UnaryOp::Cosh => return Self::new(graph, operation, u.argument),
+                UnaryOp::Sin | UnaryOp::Tan | UnaryOp::Signum | UnaryOp::Sinh | UnaryOp::Tanh | UnaryOp::Asin | UnaryOp::Atan | UnaryOp::Erf => {
+                    let inner = Self::new(graph, operation, u.argument);
+                    return Self::new(graph, UnaryOp::Negative, inner);
+                }
+                _ => {}
+            }
+        }
+
         match operation {
             UnaryOp::Exp => {
-                if let NodeType::Unary(u) = argref {
-                    if let UnaryOp::Log = u.operation {
-                        return u.argument.clone();
-                    }
+                if let NodeType::Unary(u) = argref
+                    && let UnaryOp::Log = u.operation
+                {
+                    return u.argument;
                 }
             }
             UnaryOp::Log => {
-                if let NodeType::Unary(u) = argref {
-                    if let UnaryOp::Exp = u.operation {
-                        return u.argument.clone();
-                    }
+                if let NodeType::Unary(u) = argref
+                    && let UnaryOp::Exp = u.operation
+                {
+                    return u.argument;
                 }
             }
             UnaryOp::Negative => {
-                if let NodeType::Unary(u) = argref {
-                    if let UnaryOp::Negative = u.operation {
-                        return u.argument.clone();
-                    }
-                } else if let NodeType::Constant(c) = argref {
-                    return graph.new_constant(-c.value);
+                if let NodeType::Unary(u) = argref
+                    && let UnaryOp::Negative = u.operation
+                {
+                    return u.argument;
+                }
+            }
+            UnaryOp::Exp2 => {
+                if let NodeType::Unary(u) = argref
+                    && let UnaryOp::Log2 = u.operation
+                {
+                    return u.argument;
+                }
+            }
+            UnaryOp::Log2 => {
+                if let NodeType::Unary(u) = argref
+                    && let UnaryOp::Exp2 = u.operation
+                {
+                    return u.argument;
+                }
+            }
+            UnaryOp::Abs => {
+                if let NodeType::Unary(u) = argref
+                    && let UnaryOp::Abs | UnaryOp::Sqrt | UnaryOp::Exp | UnaryOp::Exp2 | UnaryOp::Cosh = u.operation
+                {
+                    return argument;
+                }
+            }
+            UnaryOp::Sqrt => {
+                // sqrt(x * x) = |x|
+                if let NodeType::Binary(b) = argref
+                    && b.operation == BinaryOp::Mul
+                    && b.left == b.right
+                {
+                    return Self::new(graph, UnaryOp::Abs, b.left);
                 }
             }
             _ => {}
         }
 
         let base = Unary { operation, argument };
-        return graph.insert(Node::new(NodeType::Unary(base)));
+        graph.insert(Node::new(NodeType::Unary(base)))
     }
 
     pub(crate) fn differentiate(&self, graph: &mut Graph, variable: &Variable) -> *const Node {
@@ -65,47 +129,219 @@ impl Unary {
         match self.operation {
             UnaryOp::Negative => Self::new(graph, UnaryOp::Negative, arg_deriv),
             UnaryOp::Exp => {
-                let exp = Self::new(graph, UnaryOp::Exp, self.argument.clone());
+                let exp = Self::new(graph, UnaryOp::Exp, self.argument);
                 Binary::new(graph, BinaryOp::Mul, arg_deriv, exp)
             }
-            UnaryOp::Log => Binary::new(graph, BinaryOp::Div, arg_deriv, self.argument.clone()),
+            UnaryOp::Log => Binary::new(graph, BinaryOp::Div, arg_deriv, self.argument),
             UnaryOp::Sin => {
-                let cos = Self::new(graph, UnaryOp::Cos, self.argument.clone());
+                let cos = Self::new(graph, UnaryOp::Cos, self.argument);
                 Binary::new(graph, BinaryOp::Mul, arg_deriv, cos)
             }
             UnaryOp::Cos => {
-                let sin = Self::new(graph, UnaryOp::Sin, self.argument.clone());
+                let sin = Self::new(graph, UnaryOp::Sin, self.argument);
                 let negative_sin = Self::new(graph, UnaryOp::Negative, sin);
                 Binary::new(graph, BinaryOp::Mul, arg_deriv, negative_sin)
             }
             UnaryOp::Tan => {
-                let cos = Self::new(graph, UnaryOp::Cos, self.argument.clone());
+                let cos = Self::new(graph, UnaryOp::Cos, self.argument);
                 let p = graph.new_constant(-2.0);
                 let sec2 = Binary::new(graph, BinaryOp::Pow, cos, p);
                 Binary::new(graph, BinaryOp::Mul, arg_deriv, sec2)
             }
+            UnaryOp::Sqrt => {
+                let two = graph.new_constant(2.0);
+                let sqrt = Self::new(graph, UnaryOp::Sqrt, self.argument);
+                let denominator = Binary::new(graph, BinaryOp::Mul, two, sqrt);
+                Binary::new(graph, BinaryOp::Div, arg_deriv, denominator)
+            }
+            UnaryOp::Abs => {
+                let signum = Self::new(graph, UnaryOp::Signum, self.argument);
+                Binary::new(graph, BinaryOp::Mul, arg_deriv, signum)
+            }
+            // zero away from the jump, where the derivative does not exist
+            UnaryOp::Signum => graph.new_constant(0.0),
+            UnaryOp::Sinh => {
+                let cosh = Self::new(graph, UnaryOp::Cosh, self.argument);
+                Binary::new(graph, BinaryOp::Mul, arg_deriv, cosh)
+            }
+            UnaryOp::Cosh => {
+                let sinh = Self::new(graph, UnaryOp::Sinh, self.argument);
+                Binary::new(graph, BinaryOp::Mul, arg_deriv, sinh)
+            }
+            UnaryOp::Tanh => {
+                let one = graph.new_constant(1.0);
+                let tanh = Self::new(graph, UnaryOp::Tanh, self.argument);
+                let tanh2 = Binary::new(graph, BinaryOp::Mul, tanh, tanh);
+                let sech2 = Binary::new(graph, BinaryOp::Sub, one, tanh2);
+                Binary::new(graph, BinaryOp::Mul, arg_deriv, sech2)
+            }
+            UnaryOp::Asin | UnaryOp::Acos => {
+                let one = graph.new_constant(1.0);
+                let square = Binary::new(graph, BinaryOp::Mul, self.argument, self.argument);
+                let difference = Binary::new(graph, BinaryOp::Sub, one, square);
+                let root = Self::new(graph, UnaryOp::Sqrt, difference);
+                let derivative = Binary::new(graph, BinaryOp::Div, arg_deriv, root);
+                if self.operation == UnaryOp::Acos {
+                    Self::new(graph, UnaryOp::Negative, derivative)
+                } else {
+                    derivative
+                }
+            }
+            UnaryOp::Atan => {
+                let one = graph.new_constant(1.0);
+                let square = Binary::new(graph, BinaryOp::Mul, self.argument, self.argument);
+                let denominator = Binary::new(graph, BinaryOp::Add, one, square);
+                Binary::new(graph, BinaryOp::Div, arg_deriv, denominator)
+            }
+            UnaryOp::Log10 | UnaryOp::Log2 => {
+                let base = if self.operation == UnaryOp::Log10 {
+                    std::f64::consts::LN_10
+                } else {
+                    std::f64::consts::LN_2
+                };
+                let log_base = graph.new_constant(base);
+                let denominator = Binary::new(graph, BinaryOp::Mul, self.argument, log_base);
+                Binary::new(graph, BinaryOp::Div, arg_deriv, denominator)
+            }
+            UnaryOp::Exp2 => {
+                let ln2 = graph.new_constant(std::f64::consts::LN_2);
+                let exp2 = Self::new(graph, UnaryOp::Exp2, self.argument);
+                let derivative = Binary::new(graph, BinaryOp::Mul, exp2, ln2);
+                Binary::new(graph, BinaryOp::Mul, arg_deriv, derivative)
+            }
+            // d erf(x) = 2 / sqrt(pi) exp(-x^2) dx, and erfc = 1 - erf
+            UnaryOp::Erf | UnaryOp::Erfc => {
+                let sign = if self.operation == UnaryOp::Erf {
+                    1.0
+                } else {
+                    -1.0
+                };
+                let factor = graph.new_constant(sign * std::f64::consts::FRAC_2_SQRT_PI);
+                let square = Binary::new(graph, BinaryOp::Mul, self.argument, self.argument);
+                let negative = Self::new(graph, UnaryOp::Negative, square);
+                let gaussian = Self::new(graph, UnaryOp::Exp, negative);
+                let derivative = Binary::new(graph, BinaryOp::Mul, factor, gaussian);
+                Binary::new(graph, BinaryOp::Mul, arg_deriv, derivative)
+            }
+            UnaryOp::LnGamma => {
+                let digamma = Self::new(graph, UnaryOp::Digamma, self.argument);
+                Binary::new(graph, BinaryOp::Mul, arg_deriv, digamma)
+            }
+            UnaryOp::Digamma => {
+                let trigamma = Self::new(graph, UnaryOp::Trigamma, self.argument);
+                Binary::new(graph, BinaryOp::Mul, arg_deriv, trigamma)
+            }
+            UnaryOp::Trigamma => unreachable!("`trigamma` is only the second derivative of `ln_gamma`"),
         }
     }
 }
 impl UnaryOp {
+    // the operation behind a method or function of that name on `Float`
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sin" => Some(Self::Sin),
+            "cos" => Some(Self::Cos),
+            "tan" => Some(Self::Tan),
+            "exp" => Some(Self::Exp),
+            "ln" => Some(Self::Log),
+            "sqrt" => Some(Self::Sqrt),
+            "abs" => Some(Self::Abs),
+            "signum" => Some(Self::Signum),
+            "sinh" => Some(Self::Sinh),
+            "cosh" => Some(Self::Cosh),
+            "tanh" => Some(Self::Tanh),
+            "asin" => Some(Self::Asin),
+            "acos" => Some(Self::Acos),
+            "atan" => Some(Self::Atan),
+            "log10" => Some(Self::Log10),
+            "log2" => Some(Self::Log2),
+            "exp2" => Some(Self::Exp2),
+            "erf" => Some(Self::Erf),
+            "erfc" => Some(Self::Erfc),
+            // `digamma` and `trigamma` only appear as derivatives of `ln_gamma`, so the hessian never needs their own
+            "ln_gamma" => Some(Self::LnGamma),
+            _ => None,
+        }
+    }
+
+    // folds constant arguments, unless the result is not finite or needs `fastfit::special`
+    fn evaluate(&self, x: f64) -> Option<f64> {
+        let value = match &self {
+            Self::Negative => -x,
+            Self::Exp => x.exp(),
+            Self::Log => x.ln(),
+            Self::Sin => x.sin(),
+            Self::Cos => x.cos(),
+            Self::Tan => x.tan(),
+            Self::Sqrt => x.sqrt(),
+            Self::Abs => x.abs(),
+            Self::Signum => x.signum(),
+            Self::Sinh => x.sinh(),
+            Self::Cosh => x.cosh(),
+            Self::Tanh => x.tanh(),
+            Self::Asin => x.asin(),
+            Self::Acos => x.acos(),
+            Self::Atan => x.atan(),
+            Self::Log10 => x.log10(),
+            Self::Log2 => x.log2(),
+            Self::Exp2 => x.exp2(),
+            Self::Erf | Self::Erfc | Self::LnGamma | Self::Digamma | Self::Trigamma => return None,
+        };
+        value.is_finite().then_some(value)
+    }
+
     pub fn cost(&self) -> usize {
         match &self {
             Self::Negative => 3,
+            Self::Abs => 3,
+            Self::Signum => 3,
+            Self::Sqrt => 20,
             Self::Sin => 100,
             Self::Cos => 100,
             Self::Tan => 100,
             Self::Exp => 100,
             Self::Log => 100,
+            Self::Sinh => 100,
+            Self::Cosh => 100,
+            Self::Tanh => 100,
+            Self::Asin => 100,
+            Self::Acos => 100,
+            Self::Atan => 100,
+            Self::Log10 => 100,
+            Self::Log2 => 100,
+            Self::Exp2 => 100,
+            Self::Erf => 150,
+            Self::Erfc => 150,
+            Self::LnGamma => 150,
+            Self::Digamma => 150,
+            Self::Trigamma => 150,
         }
     }
-    pub fn generate_rust(&self, result: Ident, argument_value: Ident) -> TokenStream {
+    pub fn generate_rust(&self, argument_value: Ident) -> TokenStream {
         match &self {
-            Self::Negative => quote! { let #result = -#argument_value; },
-            Self::Sin => quote! { let #result = #argument_value.sin(); },
-            Self::Cos => quote! { let #result = #argument_value.cos(); },
-            Self::Tan => quote! { let #result = #argument_value.tan(); },
-            Self::Exp => quote! { let #result = #argument_value.exp(); },
-            Self::Log => quote! { let #result = #argument_value.ln(); },
+            Self::Negative => quote! { -#argument_value },
+            Self::Sin => quote! { #argument_value.sin() },
+            Self::Cos => quote! { #argument_value.cos() },
+            Self::Tan => quote! { #argument_value.tan() },
+            Self::Exp => quote! { #argument_value.exp() },
+            Self::Log => quote! { #argument_value.ln() },
+            Self::Sqrt => quote! { #argument_value.sqrt() },
+            Self::Abs => quote! { #argument_value.abs() },
+            Self::Signum => quote! { #argument_value.signum() },
+            Self::Sinh => quote! { #argument_value.sinh() },
+            Self::Cosh => quote! { #argument_value.cosh() },
+            Self::Tanh => quote! { #argument_value.tanh() },
+            Self::Asin => quote! { #argument_value.asin() },
+            Self::Acos => quote! { #argument_value.acos() },
+            Self::Atan => quote! { #argument_value.atan() },
+            Self::Log10 => quote! { #argument_value.log10() },
+            Self::Log2 => quote! { #argument_value.log2() },
+            Self::Exp2 => quote! { #argument_value.exp2() },
+            Self::Erf => quote! { fastfit::special::erf(#argument_value) },
+            Self::Erfc => quote! { fastfit::special::erfc(#argument_value) },
+            Self::LnGamma => quote! { fastfit::special::ln_gamma(#argument_value) },
+            Self::Digamma => quote! { fastfit::special::digamma(#argument_value) },
+            Self::Trigamma => quote! { fastfit::special::trigamma(#argument_value) },
         }
     }
 }
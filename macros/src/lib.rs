@@ -1,8 +1,6 @@
-use std::collections::HashMap;
-
 use proc_macro::TokenStream;
-use quote::quote;
-use syn::{Ident, ItemMod, parse_macro_input, spanned::Spanned};
+use quote::{format_ident, quote};
+use syn::{Item, ItemMod, parse_macro_input};
 
 #[allow(dead_code)]
 mod expression;
@@ -10,41 +8,490 @@ mod model;
 mod parse;
 mod translate;
 
-use model::Model;
+use model::{Model, Options, VariableGraph};
 
-use crate::expression::{Graph, unary::UnaryOp};
+use crate::expression::{Graph, Node, binary::BinaryOp, unary::UnaryOp};
 
 extern crate proc_macro;
 
-fn generate_code(graph: &mut Graph) -> proc_macro2::TokenStream {
-    let dist = translate::translate_rust(graph, "_dist".to_string(), false, false);
+// `factors` multiply to the density, and the likelihood is built as the sum of their negative logs;
+// `model` declares the `Parameters` the generated functions take
+fn generate_code(graph: &mut Graph, factors: &[*const Node], model: &Model, num_data: usize, options: &Options) -> proc_macro2::TokenStream {
+    let parameters = model.structs.get("Parameters").unwrap().leaves("");
+    let num_parameters = parameters.len();
+    let dist = translate::translate_rust(graph, "_dist".to_string(), num_parameters, num_data, false, false);
+
+    // derivatives of the density itself, which the normalization integral needs
+    let dist_hess = match options.normalize {
+        Some(_) => {
+            graph.compute_gradient(&parameters);
+            graph.compute_hessian(&parameters);
+            translate::translate_rust(graph, "_dist_hess".to_string(), num_parameters, num_data, true, true)
+        }
+        None => quote! {},
+    };
+    let prefix = if options.normalize.is_some() {
+        "_unnormalized"
+    } else {
+        ""
+    };
 
-    let log = graph.new_unary(UnaryOp::Log, graph.value.clone().unwrap());
+    let mut log = graph.new_unary(UnaryOp::Log, factors[0]);
+    for factor in &factors[1..] {
+        let factor_log = graph.new_unary(UnaryOp::Log, *factor);
+        log = graph.new_binary(BinaryOp::Add, log, factor_log);
+    }
     graph.value = Some(graph.new_unary(UnaryOp::Negative, log));
 
-    let likelihood = translate::translate_rust(graph, "_likelihood".to_string(), false, false);
+    // the extended term `nu - N ln nu` is split into `-ln nu` per event and `nu` once per dataset
+    if !options.extended.is_empty() {
+        let total = total_yield(graph, model, &options.extended);
+        let log_total = graph.new_unary(UnaryOp::Log, total);
+        graph.value = Some(graph.new_binary(BinaryOp::Sub, graph.value.unwrap(), log_total));
+    }
+
+    let likelihood = translate::translate_rust(graph, format!("{}_likelihood", prefix), num_parameters, num_data, false, false);
+    let likelihood_batch = translate::translate_batch(graph, format!("{}_likelihood_batch", prefix), num_parameters, num_data, false, false);
+    let likelihood_split = translate::translate_split(graph, format!("{}_likelihood", prefix), num_parameters, num_data, false, false);
 
-    graph.compute_gradient();
-    let gradient = translate::translate_rust(graph, "_grad".to_string(), true, false);
+    graph.compute_gradient(&parameters);
+    let gradient = translate::translate_rust(graph, format!("{}_grad", prefix), num_parameters, num_data, true, false);
+    let gradient_batch = translate::translate_batch(graph, format!("{}_grad_batch", prefix), num_parameters, num_data, true, false);
+    let gradient_split = translate::translate_split(graph, format!("{}_grad", prefix), num_parameters, num_data, true, false);
 
-    graph.compute_hessian();
-    let hessian = translate::translate_rust(graph, "_hess".to_string(), true, true);
+    graph.compute_hessian(&parameters);
+    let hessian = translate::translate_rust(graph, format!("{}_hess", prefix), num_parameters, num_data, true, true);
+    let hessian_batch = translate::translate_batch(graph, format!("{}_hess_batch", prefix), num_parameters, num_data, true, true);
+    let hessian_split = translate::translate_split(graph, format!("{}_hess", prefix), num_parameters, num_data, true, true);
+
+    let normalization = match options.normalize {
+        Some(points) => generate_normalization(num_parameters, num_data, points),
+        None => quote! {},
+    };
+
+    let extended = generate_extended(model, &options.extended);
 
     quote! {
         #dist
+        #dist_hess
         #likelihood
         #gradient
         #hessian
+        #likelihood_batch
+        #gradient_batch
+        #hessian_batch
+        #likelihood_split
+        #gradient_split
+        #hessian_split
+        #normalization
+        #extended
+    }
+}
+
+// sum of the yield parameters, built from the same variable nodes as `distribution` uses
+fn total_yield(graph: &mut Graph, model: &Model, yields: &[String]) -> *const Node {
+    let mut total = None;
+    for name in yields {
+        let variable = parse::parameter_variable(graph, model, name);
+        total = Some(match total {
+            Some(sum) => graph.new_binary(BinaryOp::Add, sum, variable),
+            None => variable,
+        });
+    }
+    total.unwrap()
+}
+
+// the once-per-dataset part `nu` of the extended likelihood, with its gradient and packed hessian
+fn generate_extended(model: &Model, yields: &[String]) -> proc_macro2::TokenStream {
+    if yields.is_empty() {
+        return quote! {};
+    }
+    let parameters = model.structs.get("Parameters").unwrap().leaves("");
+    let num_parameters = parameters.len();
+    let num_hessian = num_parameters * (num_parameters + 1) / 2;
+    let indices: Vec<usize> = yields.iter().map(|name| parameters.iter().position(|p| p == name).unwrap()).collect();
+    let gradient_indices = indices.clone();
+    quote! {
+        pub fn _extended(parameters: [Float; #num_parameters]) -> (Float, [Float; #num_parameters], [Float; #num_hessian]) {
+            let mut value = 0.0;
+            let mut gradient = [0.0; #num_parameters];
+            #(
+                value += parameters[#indices];
+                gradient[#gradient_indices] = 1.0;
+            )*
+            (value, gradient, [0.0; #num_hessian])
+        }
+    }
+}
+
+// adds `ln N(parameters)`, with `N` the integral of `_dist` over `DATA_DOMAIN`, to the unnormalized likelihood and its derivatives;
+// `ln N` is computed once per parameter point in the prologues and handed to the per-event functions through their cache
+fn generate_normalization(num_parameters: usize, num_data: usize, points: usize) -> proc_macro2::TokenStream {
+    let num_hessian = num_parameters * (num_parameters + 1) / 2;
+    quote! {
+        pub fn _log_normalization(parameters: [Float; #num_parameters]) -> Float {
+            fastfit::fitter::log_integral(|data| _dist(parameters, data), &DATA_DOMAIN, #points)
+        }
+
+        pub fn _normalization(parameters: [Float; #num_parameters]) -> (Float, [Float; #num_parameters], [Float; #num_hessian]) {
+            fastfit::fitter::log_normalization(|data| _dist_hess(parameters, data), &DATA_DOMAIN, #points)
+        }
+
+        pub fn _likelihood(parameters: [Float; #num_parameters], data: [Float; #num_data]) -> Float {
+            _likelihood_event(&_likelihood_prologue(parameters), data)
+        }
+
+        pub fn _grad(parameters: [Float; #num_parameters], data: [Float; #num_data]) -> (Float, [Float; #num_parameters]) {
+            _grad_event(&_grad_prologue(parameters), data)
+        }
+
+        pub fn _hess(parameters: [Float; #num_parameters], data: [Float; #num_data]) -> (Float, [Float; #num_parameters], [Float; #num_hessian]) {
+            _hess_event(&_hess_prologue(parameters), data)
+        }
+
+        pub fn _likelihood_prologue(parameters: [Float; #num_parameters]) -> Vec<Float> {
+            let mut cache = _unnormalized_likelihood_prologue(parameters);
+            cache.push(_log_normalization(parameters));
+            cache
+        }
+
+        pub fn _likelihood_event(cache: &[Float], data: [Float; #num_data]) -> Float {
+            let (norm, cache) = cache.split_last().unwrap();
+            _unnormalized_likelihood_event(cache, data) + norm
+        }
+
+        pub fn _grad_prologue(parameters: [Float; #num_parameters]) -> Vec<Float> {
+            let mut cache = _unnormalized_grad_prologue(parameters);
+            let (norm, norm_gradient, _) = _normalization(parameters);
+            cache.push(norm);
+            cache.extend(norm_gradient);
+            cache
+        }
+
+        pub fn _grad_event(cache: &[Float], data: [Float; #num_data]) -> (Float, [Float; #num_parameters]) {
+            let (cache, norm) = cache.split_at(cache.len() - 1 - #num_parameters);
+            let (value, mut gradient) = _unnormalized_grad_event(cache, data);
+            gradient.iter_mut().zip(&norm[1..]).for_each(|(g, n)| *g += n);
+            (value + norm[0], gradient)
+        }
+
+        pub fn _hess_prologue(parameters: [Float; #num_parameters]) -> Vec<Float> {
+            let mut cache = _unnormalized_hess_prologue(parameters);
+            let (norm, norm_gradient, norm_hessian) = _normalization(parameters);
+            cache.push(norm);
+            cache.extend(norm_gradient);
+            cache.extend(norm_hessian);
+            cache
+        }
+
+        pub fn _hess_event(cache: &[Float], data: [Float; #num_data]) -> (Float, [Float; #num_parameters], [Float; #num_hessian]) {
+            let (cache, norm) = cache.split_at(cache.len() - 1 - #num_parameters - #num_hessian);
+            let (value, mut gradient, mut hessian) = _unnormalized_hess_event(cache, data);
+            gradient.iter_mut().zip(&norm[1..]).for_each(|(g, n)| *g += n);
+            hessian.iter_mut().zip(&norm[1 + #num_parameters..]).for_each(|(h, n)| *h += n);
+            (value + norm[0], gradient, hessian)
+        }
+
+        pub fn _likelihood_batch(parameters: [Float; #num_parameters], columns: [&[Float]; #num_data]) -> Float {
+            let events = columns.first().map_or(0, |column| column.len()) as Float;
+            _unnormalized_likelihood_batch(parameters, columns) + events * _log_normalization(parameters)
+        }
+
+        pub fn _grad_batch(parameters: [Float; #num_parameters], columns: [&[Float]; #num_data]) -> (Float, [Float; #num_parameters]) {
+            let events = columns.first().map_or(0, |column| column.len()) as Float;
+            let (value, mut gradient) = _unnormalized_grad_batch(parameters, columns);
+            let (norm, norm_gradient, _) = _normalization(parameters);
+            gradient.iter_mut().zip(norm_gradient).for_each(|(g, n)| *g += events * n);
+            (value + events * norm, gradient)
+        }
+
+        pub fn _hess_batch(parameters: [Float; #num_parameters], columns: [&[Float]; #num_data]) -> (Float, [Float; #num_parameters], [Float; #num_hessian]) {
+            let events = columns.first().map_or(0, |column| column.len()) as Float;
+            let (value, mut gradient, mut hessian) = _unnormalized_hess_batch(parameters, columns);
+            let (norm, norm_gradient, norm_hessian) = _normalization(parameters);
+            gradient.iter_mut().zip(norm_gradient).for_each(|(g, n)| *g += events * n);
+            hessian.iter_mut().zip(norm_hessian).for_each(|(h, n)| *h += events * n);
+            (value + events * norm, gradient, hessian)
+        }
+    }
+}
+
+// names and sizes of the array layouts, with conversions from and to the user's structs
+fn generate_layout(model: &Model) -> proc_macro2::TokenStream {
+    let parameters = model.structs.get("Parameters").unwrap();
+    let parameter_names = parameters.leaves("");
+    let num_parameters = parameter_names.len();
+    let parameter_conversions = generate_conversions(parameters);
+    let data = match model.structs.get("Data") {
+        Some(data) => {
+            let data_names = data.leaves("");
+            let num_data = data_names.len();
+            let data_conversions = generate_conversions(data);
+            quote! {
+                pub const DATA_NAMES: [&str; #num_data] = [#(#data_names),*];
+                pub const NUM_DATA: usize = #num_data;
+                #data_conversions
+            }
+        }
+        None => quote! {},
+    };
+    quote! {
+        pub const PARAMETER_NAMES: [&str; #num_parameters] = [#(#parameter_names),*];
+        pub const NUM_PARAMETERS: usize = #num_parameters;
+        #parameter_conversions
+        #data
+    }
+}
+
+// `to_array` and `from_array`, with the leaves in declaration order as `parse::initialize` indexes them
+fn generate_conversions(graph: &VariableGraph) -> proc_macro2::TokenStream {
+    let leaves = graph.leaves("");
+    let size = leaves.len();
+    // numeric segments are array elements
+    let accessors = leaves.iter().map(|leaf| {
+        let fields = leaf.split('.').map(|field| match field.parse::<usize>() {
+            Ok(index) => quote! { [#index] },
+            Err(_) => {
+                let field = format_ident!("{}", field);
+                quote! { .#field }
+            }
+        });
+        quote! { self #(#fields)* }
+    });
+    let literal = struct_literal(graph, &mut 0);
+    let name = format_ident!("{}", graph.name);
+    quote! {
+        impl #name {
+            pub fn to_array(&self) -> [Float; #size] {
+                [#(#accessors),*]
+            }
+
+            pub fn from_array(array: [Float; #size]) -> Self {
+                #literal
+            }
+        }
+    }
+}
+
+fn struct_literal(graph: &VariableGraph, index: &mut usize) -> proc_macro2::TokenStream {
+    if graph.array {
+        let elements = *index..*index + graph.subgraphs.len();
+        *index = elements.end;
+        return quote! { [#(array[#elements]),*] };
+    }
+    let fields: Vec<_> = graph
+        .subgraphs
+        .iter()
+        .map(|(field, subgraph)| {
+            let field = format_ident!("{}", field);
+            let value = match subgraph {
+                Some(subgraph) => struct_literal(subgraph, index),
+                None => {
+                    *index += 1;
+                    let i = *index - 1;
+                    quote! { array[#i] }
+                }
+            };
+            quote! { #field: #value }
+        })
+        .collect();
+    let name = format_ident!("{}", graph.name);
+    quote! { #name { #(#fields),* } }
+}
+
+// the zero-sized `Model` with its `fastfit::Model` and `fastfit::fitter::Likelihood` implementations
+fn generate_model_type(model: &Model, options: &Options, submodel: bool) -> proc_macro2::TokenStream {
+    let generate = match submodel {
+        // events are generated from the base model at the transformed parameters
+        true => quote! {
+            let parameters = transformation(Parameters::from_array(_arrays(parameters, &[0.0; NUM_DATA]).0)).to_array();
+            fastfit::Model::generate(&super::Model, &parameters, count, random)
+        },
+        false => {
+            // the user's `generation` when there is one, otherwise accept-reject over the declared ranges
+            let events = if model.functions.contains_key("generation") {
+                quote! { (0..count).map(|_| generation(Parameters::from_array(parameters), random).to_array()).collect::<Vec<_>>() }
+            } else {
+                quote! { _generate(parameters, count, random) }
+            };
+            quote! {
+                let (parameters, _) = _arrays(parameters, &[0.0; NUM_DATA]);
+                fastfit::fitter::Dataset::from_events(&#events)
+            }
+        }
+    };
+    let extended = if options.extended.is_empty() {
+        quote! {}
+    } else {
+        quote! {
+            fn extended(&self, parameters: &[f64], gradient: &mut [f64], hessian: &mut [f64]) -> f64 {
+                let (value, g, h) = _extended(_arrays(parameters, &[0.0; NUM_DATA]).0);
+                gradient.copy_from_slice(&g);
+                hessian.copy_from_slice(&h);
+                value
+            }
+
+            fn is_extended(&self) -> bool {
+                true
+            }
+        }
+    };
+    quote! {
+        pub struct Model;
+
+        fn _arrays(parameters: &[f64], data: &[f64]) -> ([Float; NUM_PARAMETERS], [Float; NUM_DATA]) {
+            let parameters = parameters.try_into().unwrap_or_else(|_| panic!("model takes `{}` parameters, but was given `{}`", NUM_PARAMETERS, parameters.len()));
+            let data = data.try_into().unwrap_or_else(|_| panic!("model takes `{}` data values, but was given `{}`", NUM_DATA, data.len()));
+            (parameters, data)
+        }
+
+        impl fastfit::Model for Model {
+            type Parameters = Parameters;
+            type Data = Data;
+
+            const NUM_PARAMETERS: usize = NUM_PARAMETERS;
+            const NUM_DATA: usize = NUM_DATA;
+            const PARAMETER_NAMES: &'static [&'static str] = &PARAMETER_NAMES;
+            const DATA_NAMES: &'static [&'static str] = &DATA_NAMES;
+
+            fn parameters_to_vec(parameters: &Parameters) -> Vec<f64> {
+                parameters.to_array().to_vec()
+            }
+
+            fn parameters_from_slice(values: &[f64]) -> Parameters {
+                Parameters::from_array(_arrays(values, &[0.0; NUM_DATA]).0)
+            }
+
+            fn data_to_vec(data: &Data) -> Vec<f64> {
+                data.to_array().to_vec()
+            }
+
+            fn data_from_slice(values: &[f64]) -> Data {
+                Data::from_array(_arrays(&[0.0; NUM_PARAMETERS], values).1)
+            }
+
+            fn value(&self, parameters: &[f64], data: &[f64]) -> f64 {
+                let (parameters, data) = _arrays(parameters, data);
+                _dist(parameters, data)
+            }
+
+            fn nll(&self, parameters: &[f64], data: &[f64]) -> f64 {
+                let (parameters, data) = _arrays(parameters, data);
+                _likelihood(parameters, data)
+            }
+
+            fn nll_grad(&self, parameters: &[f64], data: &[f64], gradient: &mut [f64]) -> f64 {
+                let (parameters, data) = _arrays(parameters, data);
+                let (value, g) = _grad(parameters, data);
+                gradient.copy_from_slice(&g);
+                value
+            }
+
+            fn nll_grad_hess(&self, parameters: &[f64], data: &[f64], gradient: &mut [f64], hessian: &mut [f64]) -> f64 {
+                let (parameters, data) = _arrays(parameters, data);
+                let (value, g, h) = _hess(parameters, data);
+                gradient.copy_from_slice(&g);
+                hessian.copy_from_slice(&h);
+                value
+            }
+
+            fn generate(&self, parameters: &[f64], count: usize, random: &mut fastfit::Random) -> fastfit::fitter::Dataset {
+                #generate
+            }
+        }
+
+        impl fastfit::fitter::Likelihood for Model {
+            fn num_parameters(&self) -> usize {
+                NUM_PARAMETERS
+            }
+
+            fn num_data(&self) -> usize {
+                NUM_DATA
+            }
+
+            fn parameter_names(&self) -> Vec<String> {
+                PARAMETER_NAMES.iter().map(|name| name.to_string()).collect()
+            }
+
+            fn prepare(&self, parameters: &[f64], order: fastfit::fitter::Order) -> Vec<f64> {
+                let (parameters, _) = _arrays(parameters, &[0.0; NUM_DATA]);
+                match order {
+                    fastfit::fitter::Order::Value => _likelihood_prologue(parameters),
+                    fastfit::fitter::Order::Gradient => _grad_prologue(parameters),
+                    fastfit::fitter::Order::Hessian => _hess_prologue(parameters),
+                }
+            }
+
+            fn value(&self, prepared: &[f64], data: &[f64]) -> f64 {
+                _likelihood_event(prepared, _arrays(&[0.0; NUM_PARAMETERS], data).1)
+            }
+
+            fn gradient(&self, prepared: &[f64], data: &[f64], gradient: &mut [f64]) -> f64 {
+                let (value, g) = _grad_event(prepared, _arrays(&[0.0; NUM_PARAMETERS], data).1);
+                gradient.copy_from_slice(&g);
+                value
+            }
+
+            fn hessian(&self, prepared: &[f64], data: &[f64], gradient: &mut [f64], hessian: &mut [f64]) -> f64 {
+                let (value, g, h) = _hess_event(prepared, _arrays(&[0.0; NUM_PARAMETERS], data).1);
+                gradient.copy_from_slice(&g);
+                hessian.copy_from_slice(&h);
+                value
+            }
+
+            #extended
+        }
+    }
+}
+
+// accept-reject sampling of `_dist` over the declared data ranges, if every data field has one
+fn generate_sampler(model: &Model) -> proc_macro2::TokenStream {
+    let num_parameters = model.structs.get("Parameters").unwrap().leaves("").len();
+    let data = model.structs.get("Data").unwrap().leaves("");
+    let Some(ranges) = data.iter().map(|d| model.ranges.get(d)).collect::<Option<Vec<_>>>() else {
+        return quote! {};
+    };
+    let num_data = data.len();
+    let ranges = ranges.iter().map(|(lower, upper)| quote! { (#lower, #upper) });
+    quote! {
+        pub const DATA_DOMAIN: [(Float, Float); #num_data] = [#(#ranges),*];
+
+        pub fn _generate(parameters: [Float; #num_parameters], count: usize, random: &mut fastfit::Random) -> Vec<[Float; #num_data]> {
+            fastfit::accept_reject(|data| _dist(parameters, data), &DATA_DOMAIN, count, random)
+        }
     }
-    .into()
 }
 
-fn create_submodel(base_graph: &Graph, name: &String, submodel: &Model, model: &Model) -> proc_macro2::TokenStream {
-    todo!()
+// the submodel's module, with the base density rewritten in terms of its own parameters through `transformation`
+fn create_submodel(base_graph: &Graph, factors: &[*const Node], module: &ItemMod, submodel: &Model, model: &Model, options: &Options) -> proc_macro2::TokenStream {
+    let mut graph = Graph::new();
+    let parameters = match parse::build_transformation(&mut graph, submodel, model) {
+        Ok(parameters) => parameters,
+        Err(e) => return e.to_compile_error(),
+    };
+    let factors: Vec<_> = factors.iter().map(|factor| graph.substitute(*factor, &parameters)).collect();
+    graph.value = Some(graph.substitute(base_graph.value.unwrap(), &parameters));
+
+    let num_data = model.structs.get("Data").unwrap().leaves("").len();
+    let code = generate_code(&mut graph, &factors, submodel, num_data, options);
+    let layout = generate_layout(submodel);
+    let model_type = generate_model_type(submodel, options, true);
+    let name = &module.ident;
+    let items = &module.content.as_ref().unwrap().1;
+    quote! {
+        pub mod #name {
+            use super::*;
+            #(#items)*
+            #layout
+            #code
+            #model_type
+        }
+    }
 }
 
 #[proc_macro_attribute]
-pub fn define_model(_attr: TokenStream, module: TokenStream) -> TokenStream {
+pub fn define_model(attr: TokenStream, module: TokenStream) -> TokenStream {
+    let options = parse_macro_input!(attr as Options);
     let module: syn::ItemMod = parse_macro_input!(module as syn::ItemMod);
     let content = match &module.content {
         Some((_, items)) => items,
@@ -52,38 +499,77 @@ pub fn define_model(_attr: TokenStream, module: TokenStream) -> TokenStream {
             return syn::Error::new_spanned(module, "#[define_model] can only be used on module declarations").to_compile_error().into();
         }
     };
-    println!("started");
     let model_name = module.ident;
     let model = match syn::parse::<Model>(quote! { #(#content)* }.into()) {
         Ok(model) => model,
         Err(e) => return e.to_compile_error().into(),
     };
-    println!("made model");
-    println!("{:?}", model.functions.keys());
+    let parameters = model.structs.get("Parameters").unwrap().leaves("");
+    let weights = options.mixture.iter().filter_map(|(_, weight)| weight.as_ref());
+    if let Some(unknown) = options.extended.iter().chain(weights).find(|y| !parameters.contains(y)) {
+        return syn::Error::new(model_name.span(), format!("`{}` is not a field of `Parameters`", unknown)).to_compile_error().into();
+    }
+    if !options.extended.is_empty() && !model.submodels.is_empty() {
+        return syn::Error::new(model_name.span(), "extended models do not support submodels").to_compile_error().into();
+    }
+    if options.normalize.is_some()
+        && let Some(missing) = model.structs.get("Data").unwrap().leaves("").into_iter().find(|d| !model.ranges.contains_key(d))
+    {
+        return syn::Error::new(model_name.span(), format!("normalizing the model requires `#[range(lower, upper)]` on data field `{}`", missing)).to_compile_error().into();
+    }
 
-    let mut base_graph = match parse::build_graph(model.functions.get("distribution").unwrap(), &model) {
+    if options.mixture.is_empty() && options.product.is_empty() && !model.functions.contains_key("distribution") {
+        return syn::Error::new(model_name.span(), "model must define fn `distribution`, or declare a `mixture` or `product`").to_compile_error().into();
+    }
+    for component in options.mixture.iter().map(|(component, _)| component).chain(&options.product) {
+        if let Err(e) = model.check_component(component, model_name.span()) {
+            return e.to_compile_error().into();
+        }
+    }
+
+    let base_graph = if !options.mixture.is_empty() {
+        parse::build_mixture(&options.mixture, &model).map(|graph| (graph, None))
+    } else if !options.product.is_empty() {
+        parse::build_product(&options.product, &model).map(|(graph, factors)| (graph, Some(factors)))
+    } else {
+        parse::build_graph(model.functions.get("distribution").unwrap(), &model).map(|graph| (graph, None))
+    };
+    let (mut base_graph, factors) = match base_graph {
         Ok(g) => g,
         Err(e) => {
             return e.into_compile_error().into();
         }
     };
-    println!("made graph");
+    let factors = factors.unwrap_or_else(|| vec![base_graph.value.unwrap()]);
     let mut submodel_code = Vec::new();
-    for submodel in &model.submodels {
-        submodel_code.push(create_submodel(&base_graph, submodel.0, submodel.1, &model));
+    for item in content {
+        if let Item::Mod(module) = item {
+            let submodel = model.submodels.get(&module.ident.to_string()).unwrap();
+            submodel_code.push(create_submodel(&base_graph, &factors, module, submodel, &model, &options));
+        }
     }
 
-    let model_code = generate_code(&mut base_graph);
+    let num_data = model.structs.get("Data").unwrap().leaves("").len();
+    let model_code = generate_code(&mut base_graph, &factors, &model, num_data, &options);
+    let sampler_code = generate_sampler(&model);
+    let layout_code = generate_layout(&model);
+    let model_type_code = generate_model_type(&model, &options, false);
+    // submodels are emitted by `create_submodel`
+    let content: Vec<Item> = model::strip_attributes(content).into_iter().filter(|item| !matches!(item, Item::Mod(_))).collect();
     let output = quote! {
         pub mod #model_name {
             use super::*;
+            use fastfit::special::*;
+            use std::f64::consts as Constants;
             type Float = f64;
             #(#content)*
+            #layout_code
             #model_code
+            #sampler_code
+            #model_type_code
             #(#submodel_code)*
         }
     };
-    println!("{}", output);
     output.into()
 }
 //
@@ -1,64 +1,16 @@
-use std::{
-    cell::RefCell,
-    collections::{HashMap, HashSet},
-    hash::Hash,
-    rc::Rc,
-};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
 use proc_macro2::Span;
 use syn::{
-    Field, FnArg, Item, ItemFn, ItemMod, ItemStruct, Pat, PatType, Result, ReturnType, Type,
+    Expr, ExprLit, Field, FnArg, Ident, Item, ItemFn, ItemStruct, Lit, Meta, MetaNameValue, Pat, Result, ReturnType, Token, Type,
     parse::{Parse, ParseStream},
+    punctuated::Punctuated,
     spanned::Spanned,
 };
 
 use quote::quote;
 
-use crate::{Graph, expression::Node};
-
-pub struct Model {
-    pub graph: Graph,
-    pub structs: HashSet<String>,
-    pub functions: HashSet<String>,
-}
-
-impl Parse for Model {
-    fn parse(input: ParseStream) -> Result<Self> {
-        let mut functions = HashMap::new();
-        let mut structs = HashMap::new();
-        let mut modules = HashMap::new();
-
-        let mut graph = Graph::new();
-
-        while !input.is_empty() {
-            let item: Item = input.parse()?;
-
-            match item {
-                Item::Fn(f) => {
-                    let function_name = f.sig.ident.to_string();
-                    functions.insert(function_name, f);
-                }
-                Item::Struct(s) => {
-                    let struct_name = s.ident.to_string();
-                    structs.insert(struct_name, s);
-                }
-                Item::Mod(m) => {
-                    let module_name = m.ident.to_string();
-                    modules.insert(module_name, m);
-                }
-                _ => {
-                    return Err(syn::Error::new(item.span(), "unsupported model component"));
-                }
-            }
-        }
-        for (struct_name, struct_tokens) in structs {
-            for field in struct_tokens.fields {
-                let field_type = field.ty
-            }
-        }
-        todo!()
-    }
-}
+use crate::Graph;
 
 //
 // pub struct Model {
@@ -454,48 +406,146 @@ pub struct Model {
     pub structs: HashMap<String, Rc<VariableGraph>>,
     pub functions: HashMap<String, Function>,
     pub submodels: HashMap<String, Self>,
+    pub ranges: HashMap<String, (Expr, Expr)>,
 }
 
 #[derive(Debug)]
 pub struct VariableGraph {
     pub name: String,
-    pub subgraphs: HashMap<String, Option<Rc<VariableGraph>>>,
+    pub subgraphs: Vec<(String, Option<Rc<VariableGraph>>)>,
+    // a `[Float; N]` field, whose subgraphs are its elements named `0` to `N - 1`
+    pub array: bool,
 }
 
 impl VariableGraph {
     fn build(root: &ItemStruct, structs: &HashMap<String, ItemStruct>, graphs: &mut HashMap<String, Rc<VariableGraph>>) -> Result<Rc<Self>> {
         let name = root.ident.to_string();
-        let mut subgraphs = HashMap::new();
+        let mut subgraphs = Vec::new();
         for field in &root.fields {
             let field_span = field.span();
             let field_name = field.ident.clone().unwrap().to_string();
             let field_ty = &field.ty;
             let field_type = quote!(#field_ty).to_string();
+            if let Some(length) = array_length(field_ty) {
+                let elements = (0..length).map(|i| (i.to_string(), None)).collect();
+                subgraphs.push((field_name, Some(Rc::new(Self { name: field_type, subgraphs: elements, array: true }))));
+                continue;
+            }
             match field_type.as_str() {
                 "Float" => {
-                    subgraphs.insert(field_name, None);
+                    subgraphs.push((field_name, None));
                 }
                 "f64" => {
-                    subgraphs.insert(field_name, None);
+                    subgraphs.push((field_name, None));
                 }
                 _ => {
                     if graphs.contains_key(&field_name) {
                         let graph = graphs.get(&field_name).unwrap().clone();
-                        subgraphs.insert(field_name, Some(graph));
+                        subgraphs.push((field_name, Some(graph)));
                     } else {
                         if !structs.contains_key(&field_type) {
                             return Err(syn::Error::new(field_span, format!("unrecognized type: `{}`", field_type)));
                         }
                         let new_root = structs.get(&field_type).unwrap();
                         let graph = Self::build(new_root, structs, graphs)?;
-                        subgraphs.insert(field_name, Some(graph));
+                        subgraphs.push((field_name, Some(graph)));
+                    }
+                }
+            }
+        }
+        graphs.insert(name.clone(), Rc::new(Self { name: name.clone(), subgraphs, array: false }));
+        Ok(graphs.get(&name).unwrap().clone())
+    }
+
+    // dotted names of every `Float` reachable from this struct, in declaration order
+    pub fn leaves(&self, prefix: &str) -> Vec<String> {
+        let mut leaves = Vec::new();
+        for (field, subgraph) in &self.subgraphs {
+            let name = if prefix.is_empty() {
+                field.clone()
+            } else {
+                format!("{}.{}", prefix, field)
+            };
+            match subgraph {
+                Some(graph) => leaves.extend(graph.leaves(&name)),
+                None => leaves.push(name),
+            }
+        }
+        leaves
+    }
+}
+
+// the length of a `[Float; N]` field
+fn array_length(ty: &Type) -> Option<usize> {
+    let Type::Array(array) = ty else {
+        return None;
+    };
+    let element = &array.elem;
+    if !matches!(quote!(#element).to_string().as_str(), "Float" | "f64") {
+        return None;
+    }
+    match &array.len {
+        Expr::Lit(ExprLit { lit: Lit::Int(length), .. }) => length.base10_parse().ok(),
+        _ => None,
+    }
+}
+
+fn parse_range(field: &Field) -> Result<Option<(Expr, Expr)>> {
+    let Some(attribute) = field.attrs.iter().find(|a| a.path().is_ident("range")) else {
+        return Ok(None);
+    };
+    let bounds = attribute.parse_args_with(Punctuated::<Expr, Token![,]>::parse_terminated)?;
+    if bounds.len() != 2 {
+        return Err(syn::Error::new_spanned(attribute, "`range` takes a lower and an upper bound"));
+    }
+    Ok(Some((bounds[0].clone(), bounds[1].clone())))
+}
+
+// `#[range(lower, upper)]` of every leaf field reachable from `root`, keyed by dotted name
+fn collect_ranges(root: &ItemStruct, prefix: &str, structs: &HashMap<String, ItemStruct>, ranges: &mut HashMap<String, (Expr, Expr)>) -> Result<()> {
+    for field in &root.fields {
+        let field_name = field.ident.clone().unwrap().to_string();
+        let name = if prefix.is_empty() {
+            field_name
+        } else {
+            format!("{}.{}", prefix, field_name)
+        };
+        let field_ty = &field.ty;
+        let field_type = quote!(#field_ty).to_string();
+        match structs.get(&field_type) {
+            Some(substruct) => collect_ranges(substruct, &name, structs, ranges)?,
+            None => {
+                if let Some(range) = parse_range(field)? {
+                    // the range of an array applies to each element
+                    match array_length(field_ty) {
+                        Some(length) => (0..length).for_each(|i| {
+                            ranges.insert(format!("{}.{}", name, i), range.clone());
+                        }),
+                        None => {
+                            ranges.insert(name, range);
+                        }
                     }
                 }
             }
         }
-        graphs.insert(name.clone(), Rc::new(Self { name: name.clone(), subgraphs }));
-        return Ok(graphs.get(&name).unwrap().clone());
     }
+    Ok(())
+}
+
+// removes the attributes consumed by `define_model` so that the emitted module compiles
+pub fn strip_attributes(items: &[Item]) -> Vec<Item> {
+    items
+        .iter()
+        .cloned()
+        .map(|mut item| {
+            if let Item::Struct(s) = &mut item {
+                for field in s.fields.iter_mut() {
+                    field.attrs.retain(|a| !a.path().is_ident("range"));
+                }
+            }
+            item
+        })
+        .collect()
 }
 
 pub struct Function {
@@ -512,11 +562,8 @@ impl Model {
         let mut argument_types = HashMap::new();
         for arg in &f.sig.inputs {
             match arg {
-                FnArg::Typed(pat_type) => match &*pat_type.ty {
-                    Type::Path(type_path) => {
-                        let segments = &type_path.path.segments;
-                        let path_string = segments.iter().map(|segment| segment.ident.to_string()).collect::<Vec<String>>().join("::");
-                        println!("{:?}", path_string);
+                FnArg::Typed(pat_type) => match type_name(&pat_type.ty) {
+                    Some(path_string) => {
                         let var_name = if let Pat::Ident(pat_ident) = &*pat_type.pat {
                             pat_ident.ident.to_string()
                         } else {
@@ -525,7 +572,7 @@ impl Model {
                         argument_order.push(var_name.clone());
                         argument_types.insert(var_name, path_string);
                     }
-                    _ => {
+                    None => {
                         return Err(syn::Error::new(pat_type.ty.span(), "invalid argument"));
                     }
                 },
@@ -551,6 +598,21 @@ impl Model {
         Ok((argument_order, argument_types, return_string))
     }
 
+    // mixture and product components are densities of the same form as `distribution`
+    pub fn check_component(&self, name: &str, span: Span) -> Result<()> {
+        let Some(function) = self.functions.get(name) else {
+            return Err(syn::Error::new(span, format!("component `{}` is not a function of the model", name)));
+        };
+        let (argument_order, argument_types, return_type) = Self::get_types(&function.tokens)?;
+        if argument_order.len() != 2 || !argument_types.values().any(|s| s == "Parameters" || s == "self::Parameters") || !argument_types.values().any(|s| s == "Data" || s == "self::Data") {
+            return Err(syn::Error::new(function.tokens.sig.inputs.span(), format!("component `{}` must take one argument of type `Parameters` and one of type `Data`", name)));
+        }
+        if return_type != "Float" && return_type != "f64" {
+            return Err(syn::Error::new(function.tokens.sig.output.span(), format!("component `{}` return type must be `Float` or `f64`", name)));
+        }
+        Ok(())
+    }
+
     fn new(span: Span, input: Vec<Item>, base_model: bool) -> Result<Self> {
         let mut function_tokens = HashMap::new();
         let mut module_tokens = HashMap::new();
@@ -585,12 +647,6 @@ impl Model {
             if !struct_tokens.contains_key("Data") {
                 return Err(syn::Error::new(span, "model must define struct `Data`"));
             }
-            if !function_tokens.contains_key("distribution") {
-                return Err(syn::Error::new(span, "model must define fn `distribution`"));
-            }
-            if !function_tokens.contains_key("generation") {
-                return Err(syn::Error::new(span, "model must define fn `generation`"));
-            }
         } else {
             if !struct_tokens.contains_key("Parameters") {
                 return Err(syn::Error::new(span, "submodel must define struct `Parameters`"));
@@ -605,10 +661,21 @@ impl Model {
         //     .iter()
         //     .map(|(_, s)| VariableGraph::build(s, &struct_tokens, &mut structs))
         //     .collect::<Vec<_>>();
-        for (_, s) in &struct_tokens {
+        for s in struct_tokens.values() {
             VariableGraph::build(s, &struct_tokens, &mut structs)?;
         }
 
+        let mut ranges = HashMap::new();
+        if base_model {
+            collect_ranges(struct_tokens.get("Data").unwrap(), "", &struct_tokens, &mut ranges)?;
+            let data = structs.get("Data").unwrap().leaves("");
+            if !function_tokens.contains_key("generation")
+                && let Some(missing) = data.iter().find(|d| !ranges.contains_key(*d))
+            {
+                return Err(syn::Error::new(span, format!("model must define fn `generation`, or declare `#[range(lower, upper)]` on data field `{}`", missing)));
+            }
+        }
+
         let mut functions = HashMap::new();
         for (name, function) in function_tokens {
             let (argument_order, argument_types, return_type) = Self::get_types(&function)?;
@@ -622,8 +689,9 @@ impl Model {
                         return Err(syn::Error::new(function.sig.output.span(), "`distribution` return type must be `Float` or `f64`"));
                     }
                 } else if name == "generation" {
-                    if argument_order.len() != 1 || !argument_types.values().any(|s| s == "Parameters" || s == "self::Parameters") {
-                        return Err(syn::Error::new(function.sig.inputs.span(), "`generation` must take one argument of type `Parameters`"));
+                    let types: Vec<&str> = argument_order.iter().map(|a| argument_types[a].as_str()).collect();
+                    if !matches!(types[..], ["Parameters" | "self::Parameters", "&mut Random" | "&mut fastfit::Random"]) {
+                        return Err(syn::Error::new(function.sig.inputs.span(), "`generation` must take an argument of type `Parameters` and then one of type `&mut Random`"));
                     }
                     if return_type != "Data" && return_type != "self::Data" {
                         return Err(syn::Error::new(function.sig.output.span(), "`generation` return type must be `Data`"));
@@ -663,7 +731,7 @@ impl Model {
                 continue;
             }
         }
-        if errors.len() != 0 {
+        if !errors.is_empty() {
             let mut error = errors.swap_remove(0);
             for e in errors {
                 error.combine(e);
@@ -671,7 +739,7 @@ impl Model {
             return Err(error);
         }
 
-        Ok(Self { structs, functions, submodels })
+        Ok(Self { structs, functions, submodels, ranges })
     }
 }
 
@@ -682,7 +750,111 @@ impl Parse for Model {
             let item = input.parse()?;
             items.push(item);
         }
-        return Model::new(input.span(), items, true);
+        Model::new(input.span(), items, true)
+    }
+}
+
+// a path type such as `self::Parameters`, or a reference to one such as `&mut Random`
+fn type_name(ty: &Type) -> Option<String> {
+    match ty {
+        Type::Path(type_path) => Some(type_path.path.segments.iter().map(|segment| segment.ident.to_string()).collect::<Vec<String>>().join("::")),
+        Type::Reference(reference) => {
+            let mutability = if reference.mutability.is_some() {
+                "mut "
+            } else {
+                ""
+            };
+            Some(format!("&{}{}", mutability, type_name(&reference.elem)?))
+        }
+        _ => None,
+    }
+}
+
+// gauss-legendre points per data dimension when `normalize` does not give a number
+const NORMALIZATION_POINTS: usize = 32;
+
+// model-level options, given as arguments to `#[define_model(...)]`
+#[derive(Default)]
+pub struct Options {
+    // quadrature points per data dimension for normalizing `distribution` over the declared ranges
+    pub normalize: Option<usize>,
+    // dotted names of the yield parameters whose sum is the expected event count of an extended likelihood
+    pub extended: Vec<String>,
+    // component functions of a mixture with their fraction or yield parameters, in place of `distribution`;
+    // the weights are only meaningful between normalized densities, so the components must be normalized themselves
+    pub mixture: Vec<(String, Option<String>)>,
+    // component functions over disjoint data fields whose densities multiply, in place of `distribution`
+    pub product: Vec<String>,
+}
+
+impl Parse for Options {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let mut options = Self::default();
+        for meta in Punctuated::<Meta, Token![,]>::parse_terminated(input)? {
+            if meta.path().is_ident("normalize") {
+                let points = match &meta {
+                    Meta::Path(_) => NORMALIZATION_POINTS,
+                    Meta::List(list) => {
+                        let argument: MetaNameValue = list.parse_args()?;
+                        match &argument.value {
+                            Expr::Lit(ExprLit { lit: Lit::Int(points), .. }) if argument.path.is_ident("points") => points.base10_parse()?,
+                            _ => return Err(syn::Error::new_spanned(argument, "expected `points = <integer>`")),
+                        }
+                    }
+                    Meta::NameValue(_) => return Err(syn::Error::new_spanned(meta, "expected `normalize` or `normalize(points = <integer>)`")),
+                };
+                if points == 0 {
+                    return Err(syn::Error::new_spanned(meta, "normalization needs at least one quadrature point"));
+                }
+                options.normalize = Some(points);
+            } else if meta.path().is_ident("extended") {
+                let Meta::List(list) = &meta else {
+                    return Err(syn::Error::new_spanned(meta, "expected `extended(<yield parameter>, ...)`"));
+                };
+                let yields = list.parse_args_with(Punctuated::<Expr, Token![,]>::parse_terminated)?;
+                if yields.is_empty() {
+                    return Err(syn::Error::new_spanned(list, "`extended` needs at least one yield parameter"));
+                }
+                options.extended = yields.iter().map(|y| quote!(#y).to_string().replace(' ', "")).collect();
+            } else if meta.path().is_ident("mixture") {
+                let Meta::List(list) = &meta else {
+                    return Err(syn::Error::new_spanned(meta, "expected `mixture(<component> = <weight>, ...)`"));
+                };
+                for component in list.parse_args_with(Punctuated::<Expr, Token![,]>::parse_terminated)? {
+                    let (name, weight) = match &component {
+                        Expr::Assign(assign) => {
+                            let (left, right) = (&assign.left, &assign.right);
+                            (quote!(#left).to_string(), Some(quote!(#right).to_string().replace(' ', "")))
+                        }
+                        Expr::Path(path) if path.path.get_ident().is_some() => (quote!(#path).to_string(), None),
+                        _ => return Err(syn::Error::new_spanned(component, "expected `<component>` or `<component> = <weight>`")),
+                    };
+                    options.mixture.push((name, weight));
+                }
+                let unweighted = options.mixture.iter().filter(|(_, weight)| weight.is_none()).count();
+                if options.mixture.len() < 2 || unweighted > 1 || (unweighted == 1 && options.mixture.last().unwrap().1.is_some()) {
+                    return Err(syn::Error::new_spanned(list, "a mixture needs at least two components, weighted either all by yields or all but the last by fractions"));
+                }
+            } else if meta.path().is_ident("product") {
+                let Meta::List(list) = &meta else {
+                    return Err(syn::Error::new_spanned(meta, "expected `product(<component>, ...)`"));
+                };
+                let components = list.parse_args_with(Punctuated::<Ident, Token![,]>::parse_terminated)?;
+                if components.len() < 2 {
+                    return Err(syn::Error::new_spanned(list, "a product needs at least two components"));
+                }
+                options.product = components.iter().map(|c| c.to_string()).collect();
+            } else {
+                return Err(syn::Error::new_spanned(&meta, format!("unknown model option `{}`", quote!(#meta))));
+            }
+        }
+        if !options.mixture.is_empty() && !options.product.is_empty() {
+            return Err(syn::Error::new(input.span(), "a model cannot be both a `mixture` and a `product`"));
+        }
+        if options.normalize.is_some() && !options.mixture.is_empty() {
+            return Err(syn::Error::new(input.span(), "a `mixture` cannot be normalized as a whole, its components must be normalized densities"));
+        }
+        Ok(options)
     }
 }
 
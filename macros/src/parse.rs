@@ -1,11 +1,14 @@
-use std::{collections::HashMap, f64, rc::Rc};
+use std::{
+    collections::{HashMap, HashSet},
+    f64,
+    rc::Rc,
+};
 
-use quote::ToTokens;
-use syn::{Error, Expr, ExprField, ExprPath, FnArg, Ident, ItemFn, ItemStruct, Member, Pat, Result, Stmt, Type, TypePath, spanned::Spanned};
+use syn::{Error, Expr, ExprForLoop, ExprPath, ExprRange, ExprStruct, Member, Pat, PatType, RangeLimits, Result, Stmt, spanned::Spanned};
 
 use crate::{
     Model,
-    expression::{Graph, Node, binary::BinaryOp, unary::UnaryOp},
+    expression::{Graph, Node, NodeType, binary::BinaryOp, select::Comparison, unary::UnaryOp},
     model::{Function, VariableGraph},
 };
 
@@ -53,7 +56,7 @@ use crate::{
 //     graph_type: GraphType,
 // ) -> Result<(
 //     Graph,
-//     HashMap<String, Rc<Node>>,
+//     HashMap<String, *const Node>,
 //     Vec<String>,
 //     Vec<String>,
 // )> {
@@ -206,29 +209,20 @@ use crate::{
 //     Ok((graph, parameter_order, data_order))
 // }
 
-fn initialize(graph: &mut Graph, map: &mut HashMap<String, Rc<Node>>, prefix: String, argument: &Option<Rc<VariableGraph>>, parameter: bool) {
-    println!("Initializing map {:?}", map);
-    println!("prefix is {}", prefix);
-    if let Some(a) = argument {
-        println!("argument {}, {:?}", unsafe { &**a }.name, unsafe { &**a }.subgraphs);
-    };
+fn initialize(graph: &mut Graph, map: &mut HashMap<String, *const Node>, prefix: String, argument: &Option<Rc<VariableGraph>>, parameter: bool, index: &mut usize) {
     match argument {
         Some(arg) => {
             let variable_graph = arg.clone();
             for sub_argument in &variable_graph.subgraphs {
-                println!("formatting new arg");
-                println!("prefix {}", prefix);
-                println!("suffix {:?}", sub_argument);
                 let new_prefix = format!("{}.{}", prefix, sub_argument.0);
-                println!("new prefix is {}", new_prefix);
-                // if sub_argument.1.is_some() {
-                //     println!("{:?}", unsafe { &*sub_argument.1.unwrap() });
-                // }
-                initialize(graph, map, new_prefix, sub_argument.1, parameter);
+                initialize(graph, map, new_prefix, &sub_argument.1, parameter, index);
             }
         }
         None => {
-            map.insert(prefix.clone(), graph.new_variable(prefix, parameter));
+            // variables are named without the argument, so every function taking `Parameters` shares the same nodes
+            let name = prefix.split_once('.').map_or(prefix.clone(), |(_, field)| field.to_string());
+            map.insert(prefix, graph.new_variable(name, parameter, *index));
+            *index += 1;
         }
     }
 }
@@ -247,10 +241,8 @@ fn get_field_name(expr: &Expr) -> Option<String> {
     }
 }
 
-fn handle_field(expr: &Expr, map: &HashMap<String, Rc<Node>>) -> syn::Result<Rc<Node>> {
+fn handle_field(expr: &Expr, map: &HashMap<String, *const Node>) -> syn::Result<*const Node> {
     if let Some(name) = get_field_name(expr) {
-        println!("{:?}", map);
-        println!("{}", name);
         map.get(&name).cloned().ok_or_else(|| syn::Error::new_spanned(expr, format!("unknown field `{}`", name)))
     } else {
         Err(syn::Error::new_spanned(expr, "unsupported field expression"))
@@ -258,65 +250,180 @@ fn handle_field(expr: &Expr, map: &HashMap<String, Rc<Node>>) -> syn::Result<Rc<
 }
 pub fn build_graph(function: &Function, model: &Model) -> Result<Graph> {
     let mut graph = Graph::new();
-    println!("building graph for {:?}", function.name);
+    build_into(&mut graph, function, model)?;
+    Ok(graph)
+}
+
+// node of the parameter with dotted name `name`
+pub fn parameter_variable(graph: &mut Graph, model: &Model, name: &str) -> *const Node {
+    let index = model.structs.get("Parameters").unwrap().leaves("").iter().position(|p| p == name).unwrap();
+    graph.new_variable(name.to_string(), true, index)
+}
+
+// `sum_k w_k f_k` over the components, where the weights are fractions with the last one taking `1 - sum`,
+// or yields divided by their total
+pub fn build_mixture(components: &[(String, Option<String>)], model: &Model) -> Result<Graph> {
+    let mut graph = Graph::new();
+    let mut terms = Vec::new();
+    for (name, weight) in components {
+        build_into(&mut graph, model.functions.get(name).unwrap(), model)?;
+        let density = graph.value.take().unwrap();
+        terms.push((density, weight.as_ref().map(|w| parameter_variable(&mut graph, model, w))));
+    }
+
+    let mut sum = None;
+    let mut total = None;
+    for (density, weight) in terms {
+        let weight = match weight {
+            Some(weight) => {
+                total = Some(match total {
+                    Some(total) => graph.new_binary(BinaryOp::Add, total, weight),
+                    None => weight,
+                });
+                weight
+            }
+            None => {
+                let one = graph.new_constant(1.0);
+                graph.new_binary(BinaryOp::Sub, one, total.unwrap())
+            }
+        };
+        let term = graph.new_binary(BinaryOp::Mul, weight, density);
+        sum = Some(match sum {
+            Some(sum) => graph.new_binary(BinaryOp::Add, sum, term),
+            None => term,
+        });
+    }
+    let mut value = sum.unwrap();
+    if components.iter().all(|(_, weight)| weight.is_some()) {
+        value = graph.new_binary(BinaryOp::Div, value, total.unwrap());
+    }
+    graph.value = Some(value);
+    Ok(graph)
+}
+
+// product of the component densities, returned along with its factors so that the likelihood can sum their logs
+pub fn build_product(components: &[String], model: &Model) -> Result<(Graph, Vec<*const Node>)> {
+    let mut graph = Graph::new();
+    let mut factors: Vec<*const Node> = Vec::new();
+    let mut fields: Vec<(&String, HashSet<String>)> = Vec::new();
+    for name in components {
+        let function = model.functions.get(name).unwrap();
+        build_into(&mut graph, function, model)?;
+        let factor = graph.value.take().unwrap();
+        let data = graph.data_variables(factor);
+        if let Some((other, shared)) = fields.iter().find_map(|(other, f)| f.intersection(&data).next().map(|shared| (other, shared))) {
+            return Err(Error::new(function.tokens.sig.ident.span(), format!("product components must use disjoint data fields, but `{}` and `{}` both use `{}`", other, name, shared)));
+        }
+        fields.push((name, data));
+        factors.push(factor);
+    }
+    let product = factors[1..].iter().fold(factors[0], |product, factor| graph.new_binary(BinaryOp::Mul, product, *factor));
+    graph.value = Some(product);
+    Ok((graph, factors))
+}
+
+// variable nodes for every leaf of the arguments of `function`, keyed by their path from the argument
+fn arguments(graph: &mut Graph, function: &Function, model: &Model) -> HashMap<String, *const Node> {
     let mut map = HashMap::new();
     let function_arguments = &function.argument_order;
-    println!("Argument types: {:?}", model.structs);
+    // variables are indexed in declaration order, which fixes the layout of the generated arrays
+    let mut parameter_index = 0;
+    let mut data_index = 0;
     for arg in function_arguments {
         match function.argument_types.get(arg).unwrap().as_str() {
             "Float" => {
-                initialize(&mut graph, &mut map, arg.clone(), &None, true);
+                initialize(graph, &mut map, arg.clone(), &None, true, &mut parameter_index);
             }
             "f64" => {
-                initialize(&mut graph, &mut map, arg.clone(), &None, true);
+                initialize(graph, &mut map, arg.clone(), &None, true, &mut parameter_index);
             }
 
             other => {
-                println!("Other: {}", other);
-                println!("{:?}", model.structs.keys());
-                println!("prefix: {:?}, arg: {:?}", arg, model.structs.get(other).unwrap().subgraphs);
-                initialize(&mut graph, &mut map, arg.clone(), &Some(model.structs.get(other).unwrap().clone()), other == "Parameter");
+                let other = other.trim_start_matches("self::");
+                let parameter = other == "Parameters";
+                let index = if parameter {
+                    &mut parameter_index
+                } else {
+                    &mut data_index
+                };
+                initialize(graph, &mut map, arg.clone(), &Some(model.structs.get(other).unwrap().clone()), parameter, index);
             }
         }
     }
-    println!("completed initialization");
-    println!("map value is {:?}", map);
-
-    let function_tokens = &function.tokens;
+    map
+}
 
-    for statement in &function_tokens.block.stmts {
-        println!("{}", statement.to_token_stream());
+// parameters of the base model as nodes of `graph`, computed by the submodel's `transformation` from its own parameters
+pub fn build_transformation(graph: &mut Graph, submodel: &Model, model: &Model) -> Result<HashMap<String, *const Node>> {
+    let function = submodel.functions.get("transformation").unwrap();
+    let mut map = arguments(graph, function, submodel);
+    let mut parameters = HashMap::new();
+    for statement in &function.tokens.block.stmts {
         match statement {
             Stmt::Local(local) => {
-                println!("case 1");
-                if let Pat::Ident(pattern_ident) = &local.pat {
-                    if let Some(init) = &local.init {
-                        println!("building node");
-                        let result = build_node(&mut graph, &map, &init.expr, model)?;
-                        println!("built node");
-                        map.insert(pattern_ident.ident.to_string(), result);
-                    }
+                if let (Pat::Ident(pattern_ident), Some(init)) = (&local.pat, &local.init) {
+                    let result = build_node(graph, &map, &init.expr, submodel)?;
+                    map.insert(pattern_ident.ident.to_string(), result);
                 }
             }
-            Stmt::Expr(expr, ..) => {
-                println!("case 2");
-                let value = build_node(&mut graph, &map, &expr, model)?;
-                graph.value = Some(value);
-            }
+            Stmt::Expr(Expr::Struct(literal), None) => build_fields(graph, &map, literal, "", submodel, &mut parameters)?,
             _ => {
-                return Err(Error::new_spanned(statement, "unsupported statement"));
+                return Err(Error::new_spanned(statement, "`transformation` must consist of `let` statements and end in a `super::Parameters { .. }` literal"));
+            }
+        }
+    }
+    if let Some(missing) = model.structs.get("Parameters").unwrap().leaves("").into_iter().find(|p| !parameters.contains_key(p)) {
+        return Err(Error::new(function.tokens.sig.ident.span(), format!("`transformation` does not set parameter `{}`", missing)));
+    }
+    Ok(parameters)
+}
+
+// nodes of the leaves of a possibly nested struct literal, keyed by dotted name
+fn build_fields(graph: &mut Graph, map: &HashMap<String, *const Node>, literal: &ExprStruct, prefix: &str, model: &Model, fields: &mut HashMap<String, *const Node>) -> Result<()> {
+    for field in &literal.fields {
+        let Member::Named(ident) = &field.member else {
+            return Err(Error::new_spanned(field, "expected a named field"));
+        };
+        let name = if prefix.is_empty() {
+            ident.to_string()
+        } else {
+            format!("{}.{}", prefix, ident)
+        };
+        match &field.expr {
+            Expr::Struct(inner) => build_fields(graph, map, inner, &name, model, fields)?,
+            Expr::Array(array) => {
+                for (i, element) in array.elems.iter().enumerate() {
+                    let node = build_node(graph, map, element, model)?;
+                    fields.insert(format!("{}.{}", name, i), node);
+                }
+            }
+            expr => {
+                let node = build_node(graph, map, expr, model)?;
+                fields.insert(name, node);
             }
         }
     }
+    Ok(())
+}
+
+// adds the nodes of `function` to `graph`, leaving its result in `graph.value`
+fn build_into(graph: &mut Graph, function: &Function, model: &Model) -> Result<()> {
+    let mut map = arguments(graph, function, model);
+
+    let function_tokens = &function.tokens;
+
+    if let Some(value) = build_statements(graph, &mut map, &function_tokens.block.stmts, model)? {
+        graph.value = Some(value);
+    }
     if graph.value.is_none() {
-        return Err(Error::new(function_tokens.span(), "function must return a value"));
+        return Err(Error::new(function_tokens.span(), format!("`{}` must return a value", function.name)));
     }
-    println!("completed graph for {}, {:?}", function.name, graph);
-    Ok(graph)
+    Ok(())
 }
 
-fn build_node(graph: &mut Graph, map: &HashMap<String, Rc<Node>>, expr: &Expr, model: &Model) -> Result<Rc<Node>> {
+fn build_node(graph: &mut Graph, map: &HashMap<String, *const Node>, expr: &Expr, model: &Model) -> Result<*const Node> {
     match expr {
+        Expr::Binary(expr_bin) if Comparison::from_operator(&expr_bin.op).is_some() => Err(syn::Error::new_spanned(expr_bin, "comparisons are only supported as `if` conditions")),
         Expr::Binary(expr_bin) => {
             let left = build_node(graph, map, &expr_bin.left, model)?;
             let right = build_node(graph, map, &expr_bin.right, model)?;
@@ -327,26 +434,24 @@ fn build_node(graph: &mut Graph, map: &HashMap<String, Rc<Node>>, expr: &Expr, m
                 syn::BinOp::Mul(_) => BinaryOp::Mul,
                 syn::BinOp::Div(_) => BinaryOp::Div,
                 _ => {
-                    return Err(syn::Error::new_spanned(&expr_bin.op, "operation not supported"));
+                    return Err(syn::Error::new_spanned(expr_bin.op, "operation not supported"));
                 }
             };
-            return Ok(graph.new_binary(binop, left, right));
+            Ok(graph.new_binary(binop, left, right))
         }
 
         Expr::Path(ExprPath { path, .. }) => {
             let segments: Vec<_> = path.segments.iter().collect();
             if segments.len() == 1 {
-                Ok(map.get(&path.segments[0].ident.to_string()).unwrap().clone())
+                Ok(*map.get(&path.segments[0].ident.to_string()).unwrap())
             } else if segments.len() == 2 && segments[0].ident == "Constants" {
                 match segments[1].ident.to_string().as_str() {
-                    "PI" => return Ok(graph.new_constant(f64::consts::PI)),
-                    "E" => return Ok(graph.new_constant(f64::consts::E)),
-                    _ => {
-                        return Err(syn::Error::new_spanned(expr, format!("unsupported constant: {}", segments[1].ident.to_string())));
-                    }
+                    "PI" => Ok(graph.new_constant(f64::consts::PI)),
+                    "E" => Ok(graph.new_constant(f64::consts::E)),
+                    _ => Err(syn::Error::new_spanned(expr, format!("unsupported constant: {}", segments[1].ident))),
                 }
             } else {
-                Err(syn::Error::new_spanned(expr, format!("unsupported constant")))
+                Err(syn::Error::new_spanned(expr, "unsupported constant".to_string()))
             }
         }
 
@@ -378,6 +483,22 @@ fn build_node(graph: &mut Graph, map: &HashMap<String, Rc<Node>>, expr: &Expr, m
             _ => Err(syn::Error::new_spanned(lit, "unsupported literal")),
         },
         Expr::Paren(inner) => build_node(graph, map, &inner.expr, model),
+        // elements of arrays, at indices known when the macro runs
+        Expr::Index(expr_index) => {
+            let Some(base) = get_field_name(&expr_index.expr) else {
+                return Err(syn::Error::new_spanned(&expr_index.expr, "only arrays of parameters, data or `let` bindings can be indexed"));
+            };
+            let Some(index) = constant_integer(&expr_index.index, map) else {
+                return Err(syn::Error::new_spanned(&expr_index.index, "index must be a constant integer or a loop variable"));
+            };
+            map.get(&format!("{}.{}", base, index)).cloned().ok_or_else(|| syn::Error::new_spanned(expr, format!("`{}` has no element `{}`", base, index)))
+        }
+        Expr::Cast(expr_cast) => {
+            if let Some(value) = constant_integer(&expr_cast.expr, map) {
+                return Ok(graph.new_constant(value as f64));
+            }
+            build_node(graph, map, &expr_cast.expr, model)
+        }
         Expr::Unary(expr_unary) => {
             if let syn::UnOp::Neg(_) = expr_unary.op {
                 let argument = build_node(graph, map, &expr_unary.expr, model)?;
@@ -399,43 +520,47 @@ fn build_node(graph: &mut Graph, map: &HashMap<String, Rc<Node>>, expr: &Expr, m
         // }
         Expr::MethodCall(method_call) => {
             let method_name = method_call.method.to_string();
+            if method_call.args.is_empty() && method_name == "sum" {
+                return build_sum(graph, map, &method_call.receiver, model);
+            }
             if method_call.args.is_empty() {
-                let unary_op = match method_name.as_str() {
-                    "sin" => UnaryOp::Sin,
-                    "cos" => UnaryOp::Cos,
-                    "tan" => UnaryOp::Tan,
-                    "exp" => UnaryOp::Exp,
-                    "ln" => UnaryOp::Log,
-                    _ => {
-                        return Err(syn::Error::new_spanned(method_call, format!("unsupported method call: `{}`", method_name)));
-                    }
+                let Some(unary_op) = UnaryOp::from_name(&method_name) else {
+                    return Err(syn::Error::new_spanned(method_call, format!("unsupported method call: `{}`", method_name)));
                 };
                 let argument = build_node(graph, map, &method_call.receiver, model)?;
                 return Ok(graph.new_unary(unary_op, argument));
             }
 
             if method_call.args.len() == 1 {
-                let binop = match method_name.as_str() {
-                    "powf" => BinaryOp::Pow,
-                    _ => {
-                        return Err(syn::Error::new_spanned(method_call, format!("unsupported method call: `{}`", method_name)));
-                    }
+                let Some(binop) = BinaryOp::from_name(&method_name) else {
+                    return Err(syn::Error::new_spanned(method_call, format!("unsupported method call: `{}`", method_name)));
                 };
                 let left = build_node(graph, map, &method_call.receiver, model)?;
-                let right = build_node(graph, map, method_call.args.first().unwrap(), model)?;
+                let right = build_right(graph, map, &binop, method_call.args.first().unwrap(), model)?;
                 return Ok(graph.new_binary(binop, left, right));
             }
 
+            // `min(max(x, lower), upper)`, so that unlike `f64::clamp` a nan `x` gives `lower`
+            if method_call.args.len() == 2 && method_name == "clamp" {
+                let argument = build_node(graph, map, &method_call.receiver, model)?;
+                let lower = build_node(graph, map, &method_call.args[0], model)?;
+                let upper = build_node(graph, map, &method_call.args[1], model)?;
+                if let (NodeType::Constant(l), NodeType::Constant(u)) = (&unsafe { &*lower }.interior, &unsafe { &*upper }.interior)
+                    && l.value > u.value
+                {
+                    return Err(syn::Error::new_spanned(&method_call.args, "`clamp` needs `lower <= upper`"));
+                }
+                let bounded = graph.new_binary(BinaryOp::Max, argument, lower);
+                return Ok(graph.new_binary(BinaryOp::Min, bounded, upper));
+            }
+
             Err(syn::Error::new_spanned(method_call, format!("unsupported method call: {}", method_name)))
         }
         Expr::Call(call) => {
-            println!("call");
             if let Expr::Path(path) = &*call.func {
-                println!("call2");
                 let function_name = path.path.segments[0].ident.to_string();
 
                 if model.functions.contains_key(&function_name) {
-                    println!("123");
                     let helper = model.functions.get(&function_name).unwrap();
                     let exists = {
                         let h = helper.graph.borrow();
@@ -445,59 +570,285 @@ fn build_node(graph: &mut Graph, map: &HashMap<String, Rc<Node>>, expr: &Expr, m
                     if !exists {
                         let g = build_graph(helper, model)?;
                         helper.graph.replace(Some(g));
-                        println!("reached");
                     }
-                    let mut inputs = Vec::new();
-                    for arg in &call.args {
-                        inputs.push(build_node(graph, map, arg, model)?);
+                    if helper.argument_types.values().any(|t| t != "Float" && t != "f64") {
+                        return Err(syn::Error::new_spanned(call, format!("helper `{}` can only take `Float` arguments", function_name)));
+                    }
+                    if call.args.len() != helper.argument_order.len() {
+                        return Err(syn::Error::new_spanned(call, format!("`{}` takes `{}` arguments, but was given `{}`", function_name, helper.argument_order.len(), call.args.len())));
+                    }
+                    // the helper's arguments are parameter variables named after them, replaced here by the call's inputs
+                    let mut inputs = HashMap::new();
+                    for (name, arg) in helper.argument_order.iter().zip(&call.args) {
+                        inputs.insert(name.clone(), build_node(graph, map, arg, model)?);
                     }
-                    // let inputs = call
-                    //     .args
-                    //     .iter()
-                    //     .map(|arg| {
-                    //         let n = build_node(graph, map, arg, model);
-                    //         if n.is_err() {
-                    //             return n.err();
-                    //         }
-                    //         return n.ok();
-                    //     })
-                    //     .collect();
-                    println!("{:?}", inputs);
 
                     let h = helper.graph.borrow();
                     let helper_graph = h.as_ref().unwrap();
-
-                    println!("{:?}", helper_graph);
-                    return Ok(graph.splice(helper_graph, inputs));
+                    return Ok(graph.substitute(helper_graph.value.unwrap(), &inputs));
                 }
 
-                let unary_op = match function_name.as_str() {
-                    "sin" => UnaryOp::Sin,
-                    "cos" => UnaryOp::Cos,
-                    "tan" => UnaryOp::Tan,
-                    "exp" => UnaryOp::Exp,
-                    "ln" => UnaryOp::Log,
-                    _ => {
-                        return Err(syn::Error::new_spanned(call, format!("unsupported function call: `{}`", function_name)));
+                let expected = match (UnaryOp::from_name(&function_name), BinaryOp::from_name(&function_name)) {
+                    (Some(unary_op), _) if call.args.len() == 1 => {
+                        let argument = build_node(graph, map, &call.args[0], model)?;
+                        return Ok(graph.new_unary(unary_op, argument));
                     }
+                    (_, Some(binop)) if call.args.len() == 2 => {
+                        let left = build_node(graph, map, &call.args[0], model)?;
+                        let right = build_right(graph, map, &binop, &call.args[1], model)?;
+                        return Ok(graph.new_binary(binop, left, right));
+                    }
+                    (Some(_), _) => 1,
+                    (_, Some(_)) => 2,
+                    (None, None) => return Err(syn::Error::new_spanned(call, format!("unsupported function call: `{}`", function_name))),
                 };
-                let argument = build_node(graph, map, &call.args[0], model)?;
-                return Ok(graph.new_unary(unary_op, argument));
+                return Err(syn::Error::new_spanned(call, format!("`{}` takes `{}` arguments, but was given `{}`", function_name, expected, call.args.len())));
             };
             Err(syn::Error::new_spanned(call, "unsupported function call"))
         }
 
+        Expr::If(expr_if) => {
+            let Some((_, else_branch)) = &expr_if.else_branch else {
+                return Err(syn::Error::new_spanned(expr_if, "`if` without `else` is unsupported"));
+            };
+            let (comparison, left, right) = build_condition(graph, map, &expr_if.cond, model)?;
+            let then = build_block(graph, map, &expr_if.then_branch, model)?;
+            let otherwise = build_node(graph, map, else_branch, model)?;
+            Ok(graph.new_select(comparison, left, right, then, otherwise))
+        }
+        Expr::Block(expr_block) => build_block(graph, map, &expr_block.block, model),
+
         Expr::Return(ret) => {
             let Some(ret_expr) = &ret.expr else {
                 return Err(syn::Error::new_spanned(ret, "`return` without value is unsupported"));
             };
             let result = build_node(graph, map, ret_expr, model)?;
-            graph.value = Some(result.clone());
+            graph.value = Some(result);
             Ok(result)
         }
         _ => Err(syn::Error::new_spanned(expr, "unsupported expression")),
     }
 }
+
+// the right operand of `binop`, which for `powi` must be known when the macro runs
+fn build_right(graph: &mut Graph, map: &HashMap<String, *const Node>, binop: &BinaryOp, argument: &Expr, model: &Model) -> Result<*const Node> {
+    if *binop == BinaryOp::Powi {
+        let Some(exponent) = constant_integer(argument, map) else {
+            return Err(syn::Error::new_spanned(argument, "`powi` needs a constant integer exponent"));
+        };
+        return Ok(graph.new_constant(exponent as f64));
+    }
+    build_node(graph, map, argument, model)
+}
+
+// a single comparison between two values
+fn build_condition(graph: &mut Graph, map: &HashMap<String, *const Node>, expr: &Expr, model: &Model) -> Result<(Comparison, *const Node, *const Node)> {
+    match expr {
+        Expr::Paren(inner) => build_condition(graph, map, &inner.expr, model),
+        Expr::Binary(expr_bin) => {
+            let Some(comparison) = Comparison::from_operator(&expr_bin.op) else {
+                return Err(syn::Error::new_spanned(expr_bin.op, "condition must be a `<`, `<=`, `>`, `>=` or `==` comparison"));
+            };
+            let left = build_node(graph, map, &expr_bin.left, model)?;
+            let right = build_node(graph, map, &expr_bin.right, model)?;
+            Ok((comparison, left, right))
+        }
+        _ => Err(syn::Error::new_spanned(expr, "condition must be a `<`, `<=`, `>`, `>=` or `==` comparison")),
+    }
+}
+
+// the value of a branch, whose `let` bindings are visible only inside it
+fn build_block(graph: &mut Graph, map: &HashMap<String, *const Node>, block: &syn::Block, model: &Model) -> Result<*const Node> {
+    let mut map = map.clone();
+    let value = build_statements(graph, &mut map, &block.stmts, model)?;
+    value.ok_or_else(|| Error::new_spanned(block, "branch must end in a value"))
+}
+
+// adds the nodes of `statements` to `graph`, updating `map` with their bindings, and returns the value they end in
+fn build_statements(graph: &mut Graph, map: &mut HashMap<String, *const Node>, statements: &[Stmt], model: &Model) -> Result<Option<*const Node>> {
+    let mut value = None;
+    for statement in statements {
+        match statement {
+            Stmt::Local(local) => {
+                let (Some(name), Some(init)) = (pattern_name(&local.pat), &local.init) else {
+                    return Err(Error::new_spanned(statement, "unsupported statement"));
+                };
+                // arrays are bound element by element, as `name.0`, `name.1`, ..
+                if let Expr::Array(array) = &*init.expr {
+                    for (i, element) in array.elems.iter().enumerate() {
+                        let result = build_node(graph, map, element, model)?;
+                        map.insert(format!("{}.{}", name, i), result);
+                    }
+                } else {
+                    let result = build_node(graph, map, &init.expr, model)?;
+                    map.insert(name, result);
+                }
+            }
+            Stmt::Expr(Expr::ForLoop(for_loop), _) => build_loop(graph, map, for_loop, model)?,
+            Stmt::Expr(Expr::Assign(assign), _) => {
+                let name = assigned_name(&assign.left, map)?;
+                let result = build_node(graph, map, &assign.right, model)?;
+                map.insert(name, result);
+            }
+            Stmt::Expr(Expr::Binary(expr_bin), _) if compound_operation(&expr_bin.op).is_some() => {
+                let name = assigned_name(&expr_bin.left, map)?;
+                let right = build_node(graph, map, &expr_bin.right, model)?;
+                let left = *map.get(&name).unwrap();
+                let result = graph.new_binary(compound_operation(&expr_bin.op).unwrap(), left, right);
+                map.insert(name, result);
+            }
+            Stmt::Expr(expr, ..) => value = Some(build_node(graph, map, expr, model)?),
+            _ => {
+                return Err(Error::new_spanned(statement, "unsupported statement"));
+            }
+        }
+    }
+    Ok(value)
+}
+
+// the name bound by `name` or `name: Type`, possibly `mut`
+fn pattern_name(pattern: &Pat) -> Option<String> {
+    match pattern {
+        Pat::Ident(pattern_ident) => Some(pattern_ident.ident.to_string()),
+        Pat::Type(PatType { pat, .. }) => pattern_name(pat),
+        _ => None,
+    }
+}
+
+// the operation of `+=`, `-=`, `*=` and `/=`
+fn compound_operation(operator: &syn::BinOp) -> Option<BinaryOp> {
+    match operator {
+        syn::BinOp::AddAssign(_) => Some(BinaryOp::Add),
+        syn::BinOp::SubAssign(_) => Some(BinaryOp::Sub),
+        syn::BinOp::MulAssign(_) => Some(BinaryOp::Mul),
+        syn::BinOp::DivAssign(_) => Some(BinaryOp::Div),
+        _ => None,
+    }
+}
+
+// the name of a variable being assigned to, which must already be bound
+fn assigned_name(expr: &Expr, map: &HashMap<String, *const Node>) -> Result<String> {
+    match get_field_name(expr) {
+        Some(name) if map.contains_key(&name) => Ok(name),
+        _ => Err(Error::new_spanned(expr, "can only assign to variables declared with `let`")),
+    }
+}
+
+// `for i in a..b { .. }` with constant bounds, unrolled by building the body once for each `i`; assignments in the body
+// to variables declared outside the loop carry over to the next iteration and past the loop
+fn build_loop(graph: &mut Graph, map: &mut HashMap<String, *const Node>, for_loop: &ExprForLoop, model: &Model) -> Result<()> {
+    let Some(variable) = pattern_name(&for_loop.pat) else {
+        return Err(Error::new_spanned(&for_loop.pat, "loop variable must be a single name"));
+    };
+    let range = constant_range(&for_loop.expr, map)?;
+
+    let declared: Vec<String> = for_loop
+        .body
+        .stmts
+        .iter()
+        .filter_map(|statement| match statement {
+            Stmt::Local(local) => pattern_name(&local.pat),
+            _ => None,
+        })
+        .chain([variable.clone()])
+        .collect();
+    for i in range {
+        let mut scope = map.clone();
+        scope.insert(variable.clone(), graph.new_constant(i as f64));
+        build_statements(graph, &mut scope, &for_loop.body.stmts, model)?;
+        for (name, node) in scope {
+            let shadowed = declared.iter().any(|d| name == *d || name.starts_with(&format!("{}.", d)));
+            if !shadowed && map.contains_key(&name) {
+                map.insert(name, node);
+            }
+        }
+    }
+    Ok(())
+}
+
+// `(a..b).map(|i| ..).sum()`, unrolled into the sum of the closure's value at each `i`
+fn build_sum(graph: &mut Graph, map: &HashMap<String, *const Node>, receiver: &Expr, model: &Model) -> Result<*const Node> {
+    let unsupported = || Error::new_spanned(receiver, "can only sum `(a..b).map(|i| ..)` over a constant range");
+    let Expr::MethodCall(map_call) = receiver else {
+        return Err(unsupported());
+    };
+    let Some(Expr::Closure(closure)) = map_call.args.first() else {
+        return Err(unsupported());
+    };
+    if map_call.method != "map" || map_call.args.len() != 1 {
+        return Err(unsupported());
+    }
+    let (1, Some(variable)) = (closure.inputs.len(), closure.inputs.first().and_then(pattern_name)) else {
+        return Err(Error::new_spanned(&closure.inputs, "closure must take a single loop variable"));
+    };
+    let range = constant_range(&map_call.receiver, map)?;
+
+    let mut sum = None;
+    for i in range {
+        let mut scope = map.clone();
+        scope.insert(variable.clone(), graph.new_constant(i as f64));
+        let term = build_node(graph, &scope, &closure.body, model)?;
+        sum = Some(match sum {
+            Some(sum) => graph.new_binary(BinaryOp::Add, sum, term),
+            None => term,
+        });
+    }
+    Ok(sum.unwrap_or_else(|| graph.new_constant(0.0)))
+}
+
+// iterations a single loop may unroll into, beyond which the graph and the generated code grow unmanageably
+const UNROLL_LIMIT: i64 = 4096;
+
+// the values of `a..b` or `a..=b`, with bounds known when the macro runs
+fn constant_range(expr: &Expr, map: &HashMap<String, *const Node>) -> Result<std::ops::Range<i64>> {
+    match expr {
+        Expr::Paren(inner) => constant_range(&inner.expr, map),
+        Expr::Range(ExprRange { start: Some(start), limits, end: Some(end), .. }) => {
+            let (Some(start), Some(end)) = (constant_integer(start, map), constant_integer(end, map)) else {
+                return Err(Error::new_spanned(expr, "range bounds must be constant integers"));
+            };
+            let range = match limits {
+                RangeLimits::HalfOpen(_) => start..end,
+                RangeLimits::Closed(_) => start..end.saturating_add(1),
+            };
+            if range.end.saturating_sub(range.start) > UNROLL_LIMIT {
+                return Err(Error::new_spanned(expr, format!("loops are unrolled, so a range can have at most `{}` values", UNROLL_LIMIT)));
+            }
+            Ok(range)
+        }
+        _ => Err(Error::new_spanned(expr, "expected a range `a..b` with constant bounds")),
+    }
+}
+
+// an integer known when the macro runs: a literal, a loop variable, or arithmetic on those
+fn constant_integer(expr: &Expr, map: &HashMap<String, *const Node>) -> Option<i64> {
+    match expr {
+        Expr::Lit(syn::ExprLit { lit: syn::Lit::Int(int), .. }) => int.base10_parse().ok(),
+        Expr::Path(_) => {
+            let node = map.get(&get_field_name(expr)?)?;
+            match &unsafe { &**node }.interior {
+                NodeType::Constant(c) if c.value.fract() == 0.0 => Some(c.value as i64),
+                _ => None,
+            }
+        }
+        Expr::Paren(inner) => constant_integer(&inner.expr, map),
+        Expr::Cast(expr_cast) => constant_integer(&expr_cast.expr, map),
+        Expr::Unary(syn::ExprUnary { op: syn::UnOp::Neg(_), expr, .. }) => constant_integer(expr, map).map(|value| -value),
+        Expr::Binary(expr_bin) => {
+            let left = constant_integer(&expr_bin.left, map)?;
+            let right = constant_integer(&expr_bin.right, map)?;
+            match &expr_bin.op {
+                syn::BinOp::Add(_) => left.checked_add(right),
+                syn::BinOp::Sub(_) => left.checked_sub(right),
+                syn::BinOp::Mul(_) => left.checked_mul(right),
+                syn::BinOp::Div(_) => left.checked_div(right),
+                syn::BinOp::Rem(_) => left.checked_rem(right),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
 //
 // pub struct ModelArgs {
 //     pub data: Vec<Ident>,
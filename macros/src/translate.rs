@@ -1,66 +1,186 @@
-use std::{collections::HashMap, rc::Rc};
+use std::collections::{HashMap, HashSet};
 
-use crate::{
-    Model,
-    expression::{Graph, Node, NodeType, binary::BinaryOp, constant::Constant, unary::UnaryOp},
-    model::VariableGraph,
-};
+use crate::expression::{Graph, Node, NodeType};
 use proc_macro2::{Span, TokenStream};
 use quote::{format_ident, quote};
 use syn::Ident;
 
-pub fn translate_rust(graph: &Graph, fn_name: String, gradient: bool, hessian: bool) -> TokenStream {
-    let mut num_params: usize = 0;
-    let mut num_data: usize = 0;
-
-    let eval_order = graph.order();
+fn node_name(node: &*const Node) -> Ident {
+    format_ident!("v{}", *node as usize)
+}
 
-    let node_name = |node: &Rc<Node>| format_ident!("v{}", node.as_ref() as *const Node as usize);
+// the value, followed by the gradient and hessian where requested
+fn outputs(graph: &Graph, gradient: bool, hessian: bool) -> Vec<*const Node> {
+    let mut outputs = vec![graph.value.unwrap()];
+    if gradient {
+        outputs.extend(&graph.gradient);
+    }
+    if hessian {
+        outputs.extend(&graph.hessian);
+    }
+    outputs
+}
 
-    let code: Vec<_> = eval_order
-        .iter()
-        .map(|node| {
-            let result_name = node_name(node);
+// written by name where exact, since clippy takes their literals for approximations
+const NAMED_CONSTANTS: [(f64, &str); 19] = [
+    (std::f64::consts::E, "E"),
+    (std::f64::consts::FRAC_1_PI, "FRAC_1_PI"),
+    (std::f64::consts::FRAC_1_SQRT_2, "FRAC_1_SQRT_2"),
+    (std::f64::consts::FRAC_2_PI, "FRAC_2_PI"),
+    (std::f64::consts::FRAC_2_SQRT_PI, "FRAC_2_SQRT_PI"),
+    (std::f64::consts::FRAC_PI_2, "FRAC_PI_2"),
+    (std::f64::consts::FRAC_PI_3, "FRAC_PI_3"),
+    (std::f64::consts::FRAC_PI_4, "FRAC_PI_4"),
+    (std::f64::consts::FRAC_PI_6, "FRAC_PI_6"),
+    (std::f64::consts::FRAC_PI_8, "FRAC_PI_8"),
+    (std::f64::consts::LN_2, "LN_2"),
+    (std::f64::consts::LN_10, "LN_10"),
+    (std::f64::consts::LOG2_10, "LOG2_10"),
+    (std::f64::consts::LOG2_E, "LOG2_E"),
+    (std::f64::consts::LOG10_2, "LOG10_2"),
+    (std::f64::consts::LOG10_E, "LOG10_E"),
+    (std::f64::consts::PI, "PI"),
+    (std::f64::consts::SQRT_2, "SQRT_2"),
+    (std::f64::consts::TAU, "TAU"),
+];
 
-            match &node.interior {
-                NodeType::Constant(number) => {
-                    let value = number.value;
-                    quote! { let #result_name = #value; }
+// assignment of a single node to its binding, with data variables read through `data`
+fn statement(node: *const Node, data: &impl Fn(usize) -> TokenStream) -> TokenStream {
+    let result_name = node_name(&node);
+    let value = match &unsafe { &*node }.interior {
+        NodeType::Constant(number) => {
+            let value = number.value;
+            match NAMED_CONSTANTS.iter().find(|(constant, _)| *constant == value.abs()) {
+                Some((_, name)) if value < 0.0 => {
+                    let name = format_ident!("{}", name);
+                    quote! { -std::f64::consts::#name }
                 }
+                Some((_, name)) => {
+                    let name = format_ident!("{}", name);
+                    quote! { std::f64::consts::#name }
+                }
+                None => quote! { #value },
+            }
+        }
+        NodeType::Variable(variable) => {
+            let index = variable.index;
+            if variable.parameter {
+                quote! { parameters[#index] }
+            } else {
+                data(index)
+            }
+        }
+        NodeType::Unary(u) => u.operation.generate_rust(node_name(&u.argument)),
+        NodeType::Binary(b) => b.operation.generate_rust(node_name(&b.left), node_name(&b.right)),
+        NodeType::Collection(_) => {
+            panic!("unable to generate rust code, collections should not appear in final graph");
+        }
+        NodeType::Select(s) => {
+            let condition = s.comparison.generate_rust(node_name(&s.left), node_name(&s.right));
+            let (then, otherwise) = (node_name(&s.then), node_name(&s.otherwise));
+            quote! { if #condition { #then } else { #otherwise } }
+        }
+    };
+    quote! { #result_name = #value; }
+}
 
-                NodeType::Variable(variable) => {
-                    if variable.parameter {
-                        num_params += 1;
-                        let parameter_index = variable.index;
-                        quote! { let #result_name = parameters[#parameter_index]; }
+// one declaration for all of `names`, which are then assigned in turn; a `let` per node would nest a scope per node,
+// and the debug info of thousands of nested scopes overflows the compiler's stack
+fn declaration(names: &[Ident]) -> TokenStream {
+    let types = names.iter().map(|_| quote! { Float });
+    quote! { let (#(#names,)*): (#(#types,)*); }
+}
+
+// selects whose branches cost more than this together evaluate only the taken one
+const BRANCHLESS_COST: usize = 40;
+
+fn is_lazy(node: &Node) -> bool {
+    match &node.interior {
+        NodeType::Select(s) => BRANCHLESS_COST < unsafe { (*s.then).cost + (*s.otherwise).cost },
+        _ => false,
+    }
+}
+
+// bindings for `order`, where the nodes needed only by one branch of an expensive select are evaluated inside that
+// branch; cheap selects evaluate both branches and pick one, which compiles without a jump
+fn statements(order: &[*const Node], outputs: &HashSet<*const Node>, data: &impl Fn(usize) -> TokenStream) -> Vec<TokenStream> {
+    let mut users: HashMap<*const Node, Vec<*const Node>> = HashMap::new();
+    for node in order {
+        for child in unsafe { &**node }.get_children() {
+            users.entry(child).or_default().push(*node);
+        }
+    }
+
+    // the branch each node is confined to, as the select and its side, or `None` for the top level; users come later in
+    // `order`, so walking it backwards places them first
+    let mut scopes: HashMap<*const Node, Option<(*const Node, bool)>> = HashMap::new();
+    for &pointer in order.iter().rev() {
+        let uses = users.get(&pointer).map_or(&[][..], Vec::as_slice);
+        let mut use_scopes = uses.iter().map(|&user_pointer| {
+            let user = unsafe { &*user_pointer };
+            match &user.interior {
+                NodeType::Select(s) if is_lazy(user) && s.left != pointer && s.right != pointer && s.then == pointer => Some((user_pointer, true)),
+                NodeType::Select(s) if is_lazy(user) && s.left != pointer && s.right != pointer && s.otherwise == pointer => Some((user_pointer, false)),
+                _ => scopes[&user_pointer],
+            }
+        });
+        let scope = match use_scopes.next() {
+            Some(first) if !outputs.contains(&pointer) && use_scopes.all(|scope| scope == first) => first,
+            _ => None,
+        };
+        scopes.insert(pointer, scope);
+    }
+
+    let mut scoped: HashMap<Option<(*const Node, bool)>, Vec<*const Node>> = HashMap::new();
+    order.iter().for_each(|node| scoped.entry(scopes[node]).or_default().push(*node));
+    let names: Vec<Ident> = order.iter().map(node_name).collect();
+    [declaration(&names)].into_iter().chain(scope_statements(None, &scoped, data)).collect()
+}
+
+fn scope_statements(scope: Option<(*const Node, bool)>, scoped: &HashMap<Option<(*const Node, bool)>, Vec<*const Node>>, data: &impl Fn(usize) -> TokenStream) -> Vec<TokenStream> {
+    let nodes = scoped.get(&scope).map_or(&[][..], Vec::as_slice);
+    nodes
+        .iter()
+        .map(|&pointer| match &unsafe { &*pointer }.interior {
+            NodeType::Select(s) if is_lazy(unsafe { &*pointer }) => {
+                let result_name = node_name(&pointer);
+                let condition = s.comparison.generate_rust(node_name(&s.left), node_name(&s.right));
+                let then = scope_statements(Some((pointer, true)), scoped, data);
+                let otherwise = scope_statements(Some((pointer, false)), scoped, data);
+                let (then_name, otherwise_name) = (node_name(&s.then), node_name(&s.otherwise));
+                quote! {
+                    #result_name = if #condition {
+                        #(#then)*
+                        #then_name
                     } else {
-                        num_data += 1;
-                        let data_index = variable.index;
-                        quote! { let #result_name = data[#data_index]; }
-                    }
-                }
-                NodeType::Unary(u) => u.operation.generate_rust(result_name, node_name(&u.argument)),
-                NodeType::Binary(b) => b.operation.generate_rust(result_name, node_name(&b.left), node_name(&b.right)),
-                NodeType::Collection(_) => {
-                    panic!("unable to generate rust code, collections should not appear in final graph");
+                        #(#otherwise)*
+                        #otherwise_name
+                    };
                 }
             }
+            _ => statement(pointer, data),
         })
-        .collect();
+        .collect()
+}
+
+pub fn translate_rust(graph: &Graph, fn_name: String, num_params: usize, num_data: usize, gradient: bool, hessian: bool) -> TokenStream {
+    let outputs = outputs(graph, gradient, hessian);
+    let eval_order = graph.order(&outputs);
+    let code = statements(&eval_order, &outputs.into_iter().collect(), &|data_index| quote! { data[#data_index] });
     let parameters = quote! {
         parameters: [Float; #num_params]
     };
     let data = quote! {
-        data: [Float; #num_params]
+        data: [Float; #num_data]
     };
 
-    let final_value_name = node_name(&graph.value.as_ref().unwrap());
+    let final_value_name = node_name(&graph.value.unwrap());
     let fn_name = syn::Ident::new(&fn_name, Span::call_site());
     if gradient {
-        let gradient_names = graph.gradient.iter().map(|id| node_name(id)).collect::<Vec<Ident>>();
+        let gradient_names = graph.gradient.iter().map(node_name).collect::<Vec<Ident>>();
         if hessian {
-            let hessian_names = graph.hessian.iter().map(|id| node_name(id)).collect::<Vec<Ident>>();
-            let num_hess = num_params * (num_params + 1) / 2 as usize;
+            let hessian_names = graph.hessian.iter().map(node_name).collect::<Vec<Ident>>();
+            let num_hess = num_params * (num_params + 1) / 2;
             let signature = quote! {
                 pub fn #fn_name(#parameters, #data) -> (f64, [f64; #num_params], [f64; #num_hess])
             };
@@ -72,7 +192,6 @@ pub fn translate_rust(graph: &Graph, fn_name: String, gradient: bool, hessian: b
                     (#final_value_name, gradient, hessian)
                 }
             }
-            .into()
         } else {
             let signature = quote! {
                 pub fn #fn_name(#parameters, #data) -> (f64, [f64; #num_params])
@@ -84,12 +203,11 @@ pub fn translate_rust(graph: &Graph, fn_name: String, gradient: bool, hessian: b
                     (#final_value_name, gradient)
                 }
             }
-            .into()
         }
     } else {
         if hessian {
-            let hessian_names = graph.hessian.iter().map(|id| node_name(id)).collect::<Vec<Ident>>();
-            let num_hess = num_params * (num_params + 1) / 2 as usize;
+            let hessian_names = graph.hessian.iter().map(node_name).collect::<Vec<Ident>>();
+            let num_hess = num_params * (num_params + 1) / 2;
             let signature = quote! {
                 pub fn #fn_name(#parameters, #data) -> (f64, [f64; #num_hess])
             };
@@ -100,7 +218,6 @@ pub fn translate_rust(graph: &Graph, fn_name: String, gradient: bool, hessian: b
                     (#final_value_name, hessian)
                 }
             }
-            .into()
         } else {
             let signature = quote! {
                 pub fn #fn_name(#parameters, #data) -> f64
@@ -111,7 +228,118 @@ pub fn translate_rust(graph: &Graph, fn_name: String, gradient: bool, hessian: b
                     #final_value_name
                 }
             }
-            .into()
+        }
+    }
+}
+
+// sums over events given as one column per data field, evaluating the nodes that do not depend on data once before the loop
+pub fn translate_batch(graph: &Graph, fn_name: String, num_params: usize, num_data: usize, gradient: bool, hessian: bool) -> TokenStream {
+    let outputs = outputs(graph, gradient, hessian);
+    let (prologue, body): (Vec<_>, Vec<_>) = graph.order(&outputs).into_iter().partition(|node| !unsafe { &**node }.data);
+
+    let outputs: HashSet<*const Node> = outputs.into_iter().collect();
+    let mut used: HashSet<*const Node> = body.iter().flat_map(|node| unsafe { &**node }.get_children()).collect();
+    used.extend(&outputs);
+    let prologue = statements(&prologue, &used, &|_| unreachable!("data variables always depend on data"));
+    let body = statements(&body, &outputs, &|data_index| quote! { columns[#data_index][event] });
+
+    let final_value_name = node_name(&graph.value.unwrap());
+    let num_hess = num_params * (num_params + 1) / 2;
+    let (gradient_indices, gradient_names): (Vec<usize>, Vec<Ident>) = match gradient {
+        true => graph.gradient.iter().map(node_name).enumerate().unzip(),
+        false => (Vec::new(), Vec::new()),
+    };
+    let (hessian_indices, hessian_names): (Vec<usize>, Vec<Ident>) = match hessian {
+        true => graph.hessian.iter().map(node_name).enumerate().unzip(),
+        false => (Vec::new(), Vec::new()),
+    };
+    let gradient_init = match gradient {
+        true => quote! { let mut gradient = [0.0; #num_params]; },
+        false => quote! {},
+    };
+    let hessian_init = match hessian {
+        true => quote! { let mut hessian = [0.0; #num_hess]; },
+        false => quote! {},
+    };
+    let (output, result) = match (gradient, hessian) {
+        (true, true) => (quote! { (f64, [f64; #num_params], [f64; #num_hess]) }, quote! { (value, gradient, hessian) }),
+        (true, false) => (quote! { (f64, [f64; #num_params]) }, quote! { (value, gradient) }),
+        _ => (quote! { f64 }, quote! { value }),
+    };
+
+    let fn_name = syn::Ident::new(&fn_name, Span::call_site());
+    quote! {
+        pub fn #fn_name(parameters: [Float; #num_params], columns: [&[Float]; #num_data]) -> #output {
+            let events = columns.first().map_or(0, |column| column.len());
+            if columns.iter().any(|column| column.len() != events) {
+                panic!("all data columns must have the same length");
+            }
+            #(#prologue)*
+            let mut value = 0.0;
+            #gradient_init
+            #hessian_init
+            for event in 0..events {
+                #(#body)*
+                value += #final_value_name;
+                #(gradient[#gradient_indices] += #gradient_names;)*
+                #(hessian[#hessian_indices] += #hessian_names;)*
+            }
+            #result
+        }
+    }
+}
+
+// a `_prologue` computing, once per evaluation, the parameter-only nodes that the per-event part reads, and an `_event`
+// function evaluating the rest from those cached values
+pub fn translate_split(graph: &Graph, fn_name: String, num_params: usize, num_data: usize, gradient: bool, hessian: bool) -> TokenStream {
+    let value = graph.value.unwrap();
+    let gradient_nodes = if gradient {
+        graph.gradient.clone()
+    } else {
+        Vec::new()
+    };
+    let hessian_nodes = if hessian {
+        graph.hessian.clone()
+    } else {
+        Vec::new()
+    };
+
+    let roots: Vec<*const Node> = [value].into_iter().chain(gradient_nodes.iter().copied()).chain(hessian_nodes.iter().copied()).collect();
+    let (prologue, body): (Vec<_>, Vec<_>) = graph.order(&roots).into_iter().partition(|node| !unsafe { &**node }.data);
+
+    let outputs: HashSet<*const Node> = roots.into_iter().collect();
+    let mut used: HashSet<*const Node> = body.iter().flat_map(|node| unsafe { &**node }.get_children()).collect();
+    used.extend(&outputs);
+    let cached: Vec<Ident> = prologue.iter().filter(|node| used.contains(node)).map(node_name).collect();
+    let cache_indices = 0..cached.len();
+    let cached_declaration = declaration(&cached);
+
+    let prologue = statements(&prologue, &used, &|_| unreachable!("data variables always depend on data"));
+    let body = statements(&body, &outputs, &|data_index| quote! { data[#data_index] });
+
+    let final_value_name = node_name(&value);
+    let gradient_names: Vec<Ident> = gradient_nodes.iter().map(node_name).collect();
+    let hessian_names: Vec<Ident> = hessian_nodes.iter().map(node_name).collect();
+    let num_hess = num_params * (num_params + 1) / 2;
+    let (output, result) = match (gradient, hessian) {
+        (true, true) => (quote! { (f64, [f64; #num_params], [f64; #num_hess]) }, quote! { (#final_value_name, [#(#gradient_names),*], [#(#hessian_names),*]) }),
+        (true, false) => (quote! { (f64, [f64; #num_params]) }, quote! { (#final_value_name, [#(#gradient_names),*]) }),
+        _ => (quote! { f64 }, quote! { #final_value_name }),
+    };
+
+    let prologue_name = format_ident!("{}_prologue", fn_name);
+    let event_name = format_ident!("{}_event", fn_name);
+    quote! {
+        pub fn #prologue_name(parameters: [Float; #num_params]) -> Vec<Float> {
+            #(#prologue)*
+            vec![#(#cached),*]
+        }
+
+        pub fn #event_name(cache: &[Float], data: [Float; #num_data]) -> #output {
+            #cached_declaration
+            #(#cached = cache[#cache_indices];)*
+            #(#body)*
+            #result
         }
     }
 }
@@ -1,7 +1,12 @@
-use std::collections::{HashMap, HashSet};
-
+pub use fitter;
 use macros::define_model;
 mod model;
+pub mod special;
+
+pub use model::{Bounds, Discrete, Distribution, Exponential, FloatingParameter, Gaussian, Model, Parameter, Random, Uniform, accept_reject};
+
+// lets code generated by `define_model` refer to `fastfit::` from within this crate
+extern crate self as fastfit;
 
 pub fn add(left: u64, right: u64) -> u64 {
     left + right
@@ -19,11 +24,12 @@ mod gaussian {
     }
 
     pub struct Data {
+        #[range(-10.0, 10.0)]
         x: Float,
     }
 
     pub fn n(sigma: Float) -> Float {
-        (2.0 * 3.1415926535) * sigma
+        (2.0 * Constants::PI) * sigma
     }
 
     pub fn norm(sigma: Float) -> Float {
@@ -35,122 +41,100 @@ mod gaussian {
         let exp = (-0.5 * ((data.x - parameters.mu.mu) / parameters.sigma).powf(2.0)).exp();
         exp * norm
     }
+}
+
+#[define_model]
+mod b0s_phi_mu_mu {
+    pub struct Parameters {
+        s: Signal,
+        b: Background,
+    }
+
+    pub struct Signal {
+        // coefficients of the angular terms 1s, 1c, 2s, 2c, 3, 4, 5, 6s, 7, 8 and 9
+        k: [Float; 11],
+        w: [Float; 11],
+        h: [Float; 11],
+        z: [Float; 11],
+        m: Float,
+        sigma_m: Float,
+        x: Float,
+        y: Float,
+        gamma: Float,
+    }
+
+    pub struct Background {
+        c0: Float,
+        c1: Float,
+        c2: Float,
+        k_m: Float,
+    }
+
+    pub struct Data {
+        ctl: Float,
+        ctk: Float,
+        phi: Float,
+        t: Float,
+        m: Float,
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn time_dependent(cosh_factor: Float, cos_factor: Float, h_i: Float, z_i: Float, x: Float, y: Float, gamma: Float, t: Float, sign: Float) -> Float {
+        let cosh = (y * gamma * t).cosh();
+        let sinh = (y * gamma * t).sinh();
+        let cos = (x * gamma * t).cos();
+        let sin = (x * gamma * t).sin();
+        cosh_factor * cosh - h_i * sinh + sign * (cos_factor * cos - z_i * sin)
+    }
 
-    pub fn generation(parameters: Parameters) -> Data {
-        todo!()
+    #[allow(clippy::needless_range_loop)]
+    pub fn distribution(p: Parameters, d: Data) -> Float {
+        let ctl2 = d.ctl * d.ctl;
+        let ctk2 = d.ctk * d.ctk;
+        let c2tl = 2.0 * ctl2 - 1.0;
+        let stk2 = 1.0 - ctk2;
+        let stl2 = 1.0 - ctl2;
+        let stl = stl2.sqrt();
+        let stk = stk2.sqrt();
+        let s2tl = 2.0 * stl * d.ctl;
+        let s2tk = 2.0 * stk * d.ctk;
+
+        let angular = [
+            stk2,
+            ctk2,
+            stk2 * c2tl,
+            ctk2 * c2tl,
+            stk2 * stl2 * (2.0 * d.phi).cos(),
+            s2tk * s2tl * d.phi.cos(),
+            s2tl * stl * d.phi.cos(),
+            stk2 * ctl2,
+            s2tk * stl * d.phi.sin(),
+            s2tk * s2tl * d.phi.sin(),
+            stk2 * stl2 * (2.0 * d.phi).sin(),
+        ];
+        // terms 5, 6s, 8 and 9 take `w` as the cosh factor and `k` as the cos factor
+        let cosh_factors = [p.s.k[0], p.s.k[1], p.s.k[2], p.s.k[3], p.s.k[4], p.s.k[5], p.s.w[6], p.s.w[7], p.s.k[8], p.s.w[9], p.s.w[10]];
+        let cos_factors = [p.s.w[0], p.s.w[1], p.s.w[2], p.s.w[3], p.s.w[4], p.s.w[5], p.s.k[6], p.s.k[7], p.s.w[8], p.s.k[9], p.s.k[10]];
+
+        let mut total = 0.0;
+        for i in 0..11 {
+            total += time_dependent(cosh_factors[i], cos_factors[i], p.s.h[i], p.s.z[i], p.s.x, p.s.y, p.s.gamma, d.t, 1.0) * angular[i];
+        }
+        (9.0 / 64.0) * total
+    }
+
+    // flat angles, with the decay time and mass of an unmixed signal
+    pub fn generation(parameters: Parameters, random: &mut Random) -> Data {
+        Data {
+            ctl: random.uniform_range(-1.0, 1.0),
+            ctk: random.uniform_range(-1.0, 1.0),
+            phi: random.uniform_range(-Constants::PI, Constants::PI),
+            t: -(1.0 - random.uniform()).ln() / parameters.s.gamma,
+            m: parameters.s.m + parameters.s.sigma_m * random.normal(),
+        }
     }
 }
 //
-// #[define_model]
-// mod b0s_phi_mu_mu {
-//     struct Parameters {
-//         s: Signal,
-//         b: Background,
-//     }
-//
-//     struct Signal {
-//         k1s: Float,
-//         k1c: Float,
-//         k2s: Float,
-//         k2c: Float,
-//         k3: Float,
-//         k4: Float,
-//         k5: Float,
-//         k6s: Float,
-//         k7: Float,
-//         k8: Float,
-//         k9: Float,
-//         w1s: Float,
-//         w1c: Float,
-//         w2s: Float,
-//         w2c: Float,
-//         w3: Float,
-//         w4: Float,
-//         w5: Float,
-//         w6s: Float,
-//         w7: Float,
-//         w8: Float,
-//         w9: Float,
-//         h1s: Float,
-//         h1c: Float,
-//         h2s: Float,
-//         h2c: Float,
-//         h3: Float,
-//         h4: Float,
-//         h5: Float,
-//         h6s: Float,
-//         h7: Float,
-//         h8: Float,
-//         h9: Float,
-//         z1s: Float,
-//         z1c: Float,
-//         z2s: Float,
-//         z2c: Float,
-//         z3: Float,
-//         z4: Float,
-//         z5: Float,
-//         z6s: Float,
-//         z7: Float,
-//         z8: Float,
-//         z9: Float,
-//         m: Float,
-//         sigma_m: Float,
-//     }
-//
-//     struct Background {
-//         c0: Float,
-//         c1: Float,
-//         c2: Float,
-//         k_m: Float,
-//     }
-//
-//     struct Data {
-//         ctl: Float,
-//         ctk: Float,
-//         phi: Float,
-//         t: Float,
-//         m: Float,
-//     }
-//
-//     fn time_dependent(cosh_factor: Float, cos_factor: Float, h_i: Float, z_i: Float, x: Float, y: Float, gamma: Float, t: Float, sign: Float) -> Float {
-//         let p = (y * gamma * t).exp();
-//         let m = (-y * gamma * t).exp();
-//         let cosh = (p + m) / 2.0;
-//         let sinh = (p - m) / 2.0;
-//         let cos = (x * gamma * t).cos();
-//         let sin = (x * gamma * t).sin();
-//         return cosh_factor * cosh - h_i * sinh + sign * (cos_factor * cos - z_i * sin);
-//     }
-//     pub fn distribution(p: Parameters, d: Data) -> Float {
-//         let ctl2 = d.ctl * d.ctl;
-//         let ctk2 = d.ctk * d.ctk;
-//         let c2tl = 2.0 * ctl2 - 1.0;
-//         let stk2 = 1.0 - ctk2;
-//         let stl2 = 1.0 - ctl2;
-//         let stl = stl2.powf(0.5);
-//         let stk = stk2.powf(0.5);
-//         let s2tl = 2.0 * stl * d.ctl;
-//         let s2tk = 2.0 * stk * d.ctk;
-//
-//         return (9.0 / 64.0)
-//             * (time_dependent(p.k1s, p.w1s, p.h1s, p.z1s, p.x, p.y, p.gamma, d.t, 1.0) * stk2
-//                 + time_dependent(p.k1c, p.w1c, p.h1c, p.z1c, p.x, p.y, p.gamma, d.t, 1.0) * ctk2
-//                 + time_dependent(p.k2s, p.w2s, p.h2s, p.z2s, p.x, p.y, p.gamma, d.t, 1.0) * stk2 * c2tl
-//                 + time_dependent(p.k2c, p.w2c, p.h2c, p.z2c, p.x, p.y, p.gamma, d.t, 1.0) * ctk2 * c2tl
-//                 + time_dependent(p.k3, p.w3, p.h3, p.z3, p.x, p.y, p.gamma, d.t, 1.0) * stk2 * stl2 * (2.0 * d.phi).cos()
-//                 + time_dependent(p.k4, p.w4, p.h4, p.z4, p.x, p.y, p.gamma, d.t, 1.0) * s2tk * s2tl * d.phi.cos()
-//                 + time_dependent(p.w5, p.k5, p.h5, p.z5, p.x, p.y, p.gamma, d.t, 1.0) * s2tl * stl * d.phi.cos()
-//                 + time_dependent(p.w6s, p.k6s, p.h6s, p.z6s, p.x, p.y, p.gamma, d.t, 1.0) * stk2 * ctl2
-//                 + time_dependent(p.k7, p.w7, p.h7, p.z7, p.x, p.y, p.gamma, d.t, 1.0) * s2tk * stl * d.phi.sin()
-//                 + time_dependent(p.w8, p.k8, p.h8, p.z8, p.x, p.y, p.gamma, d.t, 1.0) * s2tk * s2tl * d.phi.sin()
-//                 + time_dependent(p.w9, p.k9, p.h9, p.z9, p.x, p.y, p.gamma, d.t, 1.0) * stk2 * stl2 * (2.0 * d.phi).sin());
-//     }
-//
-//     pub fn generation(parameters: Parameters) -> Data {
-//         Data { ctl: 0.0, ctk: 0.0, phi: 0.0, t: 0.0, m: 0.0 }
-//     }
-// }
-//
 //
 //
 //
@@ -196,3 +180,636 @@ mod gaussian {
 //         println!("{}", result);
 //     }
 // }
+
+// models `define_model` must reject, next to the accepted form they differ from
+#[cfg(doctest)]
+mod rejected {
+    /// ```
+    /// #[macros::define_model(product(mass, time))]
+    /// mod factorized {
+    ///     pub struct Parameters { mu: Float, tau: Float }
+    ///     pub struct Data { #[range(4.0, 6.0)] m: Float, #[range(0.0, 5.0)] t: Float }
+    ///     pub fn mass(p: Parameters, d: Data) -> Float { (-(d.m - p.mu).powi(2)).exp() }
+    ///     pub fn time(p: Parameters, d: Data) -> Float { (-d.t / p.tau).exp() / p.tau }
+    /// }
+    /// # fn main() { let _ = factorized::_likelihood([5.0, 1.0], [5.0, 1.0]); }
+    /// ```
+    ///
+    /// ```compile_fail
+    /// #[macros::define_model(product(mass, time))]
+    /// mod factorized {
+    ///     pub struct Parameters { mu: Float, tau: Float }
+    ///     pub struct Data { #[range(4.0, 6.0)] m: Float, #[range(0.0, 5.0)] t: Float }
+    ///     pub fn mass(p: Parameters, d: Data) -> Float { (-(d.m - p.mu).powi(2)).exp() }
+    ///     pub fn time(p: Parameters, d: Data) -> Float { (-d.t / p.tau).exp() / p.tau * d.m }
+    /// }
+    /// # fn main() { let _ = factorized::_likelihood([5.0, 1.0], [5.0, 1.0]); }
+    /// ```
+    pub struct SharedProductFields;
+
+    /// ```compile_fail
+    /// #[macros::define_model]
+    /// mod unrolled {
+    ///     pub struct Parameters { a: Float }
+    ///     pub struct Data { #[range(0.0, 1.0)] x: Float }
+    ///     pub fn distribution(p: Parameters, d: Data) -> Float { (0..=4096).map(|i| p.a * d.x * (i as Float)).sum() }
+    /// }
+    /// # fn main() { let _ = unrolled::_likelihood([1.0], [0.5]); }
+    /// ```
+    pub struct LongLoop;
+
+    /// ```compile_fail
+    /// #[macros::define_model(normalize, mixture(signal = f, background))]
+    /// mod normalized {
+    ///     pub struct Parameters { a: Float, f: Float }
+    ///     pub struct Data { #[range(0.0, 1.0)] x: Float }
+    ///     pub fn signal(p: Parameters, d: Data) -> Float { p.a * (-p.a * d.x).exp() }
+    ///     pub fn background(p: Parameters, d: Data) -> Float { 1.0 + 0.0 * p.a * d.x }
+    /// }
+    /// # fn main() { let _ = normalized::_likelihood([1.0, 0.5], [0.5]); }
+    /// ```
+    pub struct NormalizedMixture;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    // central differences of `f` along each parameter
+    fn numerical_gradient<const P: usize>(f: impl Fn([f64; P]) -> f64, parameters: [f64; P]) -> [f64; P] {
+        std::array::from_fn(|i| {
+            let step = 1e-6 * parameters[i].abs().max(1.0);
+            let (mut up, mut down) = (parameters, parameters);
+            up[i] += step;
+            down[i] -= step;
+            (f(up) - f(down)) / (2.0 * step)
+        })
+    }
+
+    fn assert_close(value: f64, reference: f64, tolerance: f64, what: &str) {
+        assert!((value - reference).abs() <= tolerance * reference.abs().max(1.0), "{}: {} against {}", what, value, reference);
+    }
+
+    // checks `grad` and the packed `hess` against differences of `likelihood` and `grad`
+    fn assert_derivatives<const P: usize, const H: usize>(
+        likelihood: impl Fn([f64; P]) -> f64,
+        grad: impl Fn([f64; P]) -> (f64, [f64; P]),
+        hess: impl Fn([f64; P]) -> (f64, [f64; P], [f64; H]),
+        parameters: [f64; P],
+    ) {
+        let (value, gradient) = grad(parameters);
+        assert_eq!(value, likelihood(parameters));
+        let numerical = numerical_gradient(&likelihood, parameters);
+        (0..P).for_each(|i| assert_close(gradient[i], numerical[i], 1e-6, &format!("gradient {}", i)));
+
+        let (value, same_gradient, hessian) = hess(parameters);
+        assert_eq!((value, same_gradient), (likelihood(parameters), gradient));
+        let mut packed = hessian.iter();
+        for i in 0..P {
+            let numerical = numerical_gradient(|p| grad(p).1[i], parameters);
+            (i..P).for_each(|j| assert_close(*packed.next().unwrap(), numerical[j], 1e-6, &format!("hessian {} {}", i, j)));
+        }
+    }
+
+    // every function added to the graph, at arguments inside their domains
+    #[define_model]
+    mod functions {
+        pub struct Parameters {
+            a: Float,
+            b: Float,
+        }
+
+        pub struct Data {
+            #[range(0.1, 0.9)]
+            x: Float,
+        }
+
+        pub fn distribution(p: Parameters, d: Data) -> Float {
+            let u = p.a * d.x;
+            let v = p.b + d.x;
+            let roots = u.sqrt() + (v - 1.0).abs() + u.powi(3) + v.powi(-2) + u.atan2(-v) + u.hypot(v);
+            let hyperbolic = u.sinh() + v.cosh() + u.tanh();
+            let inverse = (0.5 * u).asin() + (0.5 * u).acos() + v.atan();
+            let logs = u.log10() + v.log2() + u.exp2() + u.powf(v);
+            let special = erf(u) + erfc(v) + ln_gamma(u + 1.0) + faddeeva_re(u, v) + faddeeva_im(v, u);
+            let total = roots + hyperbolic + inverse + logs + special;
+            (-0.1 * total * total).exp()
+        }
+    }
+
+    #[test]
+    fn functions_match_their_rust_counterparts() {
+        let parameters: [f64; 2] = [1.3, 0.4];
+        for x in [0.15, 0.5, 0.85] {
+            let data = [x];
+            let direct = functions::distribution(functions::Parameters::from_array(parameters), functions::Data::from_array(data));
+            assert_close(functions::_dist(parameters, data), direct, 1e-14, "density");
+            assert_close(functions::_likelihood(parameters, data), -direct.ln(), 1e-12, "likelihood");
+            assert_derivatives(|p| functions::_likelihood(p, data), |p| functions::_grad(p, data), |p| functions::_hess(p, data), parameters);
+        }
+    }
+
+    // `max` and `min` against a nan side, which is never the active one
+    #[define_model]
+    mod nan_sides {
+        pub struct Parameters {
+            a: Float,
+            b: Float,
+        }
+
+        pub struct Data {
+            #[range(0.1, 0.9)]
+            x: Float,
+        }
+
+        pub fn distribution(p: Parameters, d: Data) -> Float {
+            let nan = (d.x - 1.0).ln();
+            (-(p.a * d.x).max(nan) - nan.min(p.b * d.x) - (p.a * p.a).max(p.b)).exp()
+        }
+    }
+
+    #[test]
+    fn max_and_min_skip_nan_sides() {
+        let parameters: [f64; 2] = [0.8, 1.1];
+        for x in [0.15, 0.5, 0.85] {
+            let data = [x];
+            let direct = nan_sides::distribution(nan_sides::Parameters::from_array(parameters), nan_sides::Data::from_array(data));
+            assert_close(nan_sides::_dist(parameters, data), direct, 1e-14, "density");
+            assert_derivatives(|p| nan_sides::_likelihood(p, data), |p| nan_sides::_grad(p, data), |p| nan_sides::_hess(p, data), parameters);
+            assert_eq!(nan_sides::_grad(parameters, data).1, [x, x + 1.0]);
+        }
+    }
+
+    // `clamp`, and `max` and `min` against constants that the graph folds together
+    #[define_model]
+    mod bounded {
+        pub struct Parameters {
+            a: Float,
+            b: Float,
+        }
+
+        pub struct Data {
+            #[range(0.1, 0.9)]
+            x: Float,
+        }
+
+        pub fn distribution(p: Parameters, d: Data) -> Float {
+            let clamped = (p.a * d.x).clamp(0.2, 0.6);
+            let nested = (p.b * d.x).max(0.1).max(0.3);
+            let capped = (p.a * d.x).min(0.5);
+            let collapsed = capped.max(0.8);
+            let nan = (d.x - 1.0).ln().clamp(0.2, 0.6);
+            (-(clamped + nested + collapsed + nan + p.a.min(p.b))).exp()
+        }
+    }
+
+    #[test]
+    fn bounds_differentiate_their_active_side() {
+        for (parameters, x) in [([1.0, 1.5], 0.15), ([1.0, 1.5], 0.5), ([1.0, 1.5], 0.85), ([1.6, 0.9], 0.3), ([1.6, 0.9], 0.7)] {
+            let (a, b): (f64, f64) = (parameters[0], parameters[1]);
+            let data = [x];
+            // `clamp` gives its lower bound for nan, where `f64::clamp` keeps the nan
+            assert!(bounded::distribution(bounded::Parameters::from_array(parameters), bounded::Data::from_array(data)).is_nan());
+            let likelihood = (a * x).clamp(0.2, 0.6) + (b * x).max(0.3) + 0.8 + 0.2 + a.min(b);
+            assert_close(bounded::_likelihood(parameters, data), likelihood, 1e-14, "likelihood");
+            assert_derivatives(|p| bounded::_likelihood(p, data), |p| bounded::_grad(p, data), |p| bounded::_hess(p, data), parameters);
+
+            let inside = |value: f64, lower: f64, upper: f64| {
+                if lower < value && value < upper {
+                    x
+                } else {
+                    0.0
+                }
+            };
+            let gradient = [
+                inside(a * x, 0.2, 0.6)
+                    + if a < b {
+                        1.0
+                    } else {
+                        0.0
+                    },
+                inside(b * x, 0.3, f64::INFINITY)
+                    + if b < a {
+                        1.0
+                    } else {
+                        0.0
+                    },
+            ];
+            assert_eq!(bounded::_hess(parameters, data), (likelihood, gradient, [0.0; 3]));
+        }
+    }
+
+    // sums unrolled from half-open, closed and empty ranges
+    #[define_model]
+    mod series {
+        pub struct Parameters {
+            a: Float,
+            b: Float,
+        }
+
+        pub struct Data {
+            #[range(0.1, 0.9)]
+            x: Float,
+        }
+
+        pub fn distribution(p: Parameters, d: Data) -> Float {
+            let powers: Float = (0..4).map(|i| (p.a * d.x).powi(i)).sum();
+            let harmonic: Float = (1..=3).map(|k| p.b.powi(k) / (k as Float)).sum();
+            let empty: Float = (2..2).map(|i| p.a * (i as Float)).sum();
+            let mut closed = 0.0;
+            for j in 1..=2 {
+                closed += d.x.powi(j) * p.b;
+            }
+            (-(powers + harmonic + empty + closed)).exp()
+        }
+    }
+
+    #[test]
+    fn loops_unroll_over_their_range() {
+        let parameters: [f64; 2] = [0.7, 0.4];
+        let (a, b) = (parameters[0], parameters[1]);
+        for x in [0.15, 0.5, 0.85] {
+            let data = [x];
+            let likelihood = 1.0 + a * x + (a * x).powi(2) + (a * x).powi(3) + b + b * b / 2.0 + b.powi(3) / 3.0 + (x + x * x) * b;
+            let direct = series::distribution(series::Parameters::from_array(parameters), series::Data::from_array(data));
+            assert_close(-direct.ln(), likelihood, 1e-14, "direct likelihood");
+            assert_close(series::_likelihood(parameters, data), likelihood, 1e-14, "likelihood");
+            assert_derivatives(|p| series::_likelihood(p, data), |p| series::_grad(p, data), |p| series::_hess(p, data), parameters);
+        }
+    }
+
+    // a data threshold and a parameter one, where each branch is only valid on its own side
+    #[define_model]
+    mod piecewise {
+        pub struct Parameters {
+            a: Float,
+            b: Float,
+        }
+
+        pub struct Data {
+            #[range(0.1, 0.9)]
+            x: Float,
+        }
+
+        pub fn distribution(p: Parameters, d: Data) -> Float {
+            if d.x < 0.5 {
+                (p.a * (0.5 - d.x).ln()).exp()
+            } else if p.a * d.x >= p.b {
+                p.b * (d.x - 0.5).sqrt() + 1.0
+            } else {
+                let w = p.a * p.b;
+                w * d.x + 0.2
+            }
+        }
+    }
+
+    #[test]
+    fn branches_are_differentiated_separately() {
+        for (parameters, x) in [([1.3, 0.6], 0.2), ([1.3, 0.6], 0.8), ([1.3, 1.5], 0.8), ([1.3, 1.5], 0.5)] {
+            let data = [x];
+            let direct = piecewise::distribution(piecewise::Parameters::from_array(parameters), piecewise::Data::from_array(data));
+            assert!(direct.is_finite());
+            assert_close(piecewise::_dist(parameters, data), direct, 1e-14, "density");
+            assert_close(piecewise::_likelihood(parameters, data), -direct.ln(), 1e-14, "likelihood");
+            assert_derivatives(|p| piecewise::_likelihood(p, data), |p| piecewise::_grad(p, data), |p| piecewise::_hess(p, data), parameters);
+        }
+        // only the active branch contributes, so `b` has no effect below the data threshold
+        let (_, gradient, hessian) = piecewise::_hess([1.3, 0.6], [0.2]);
+        assert_eq!((gradient[1], hessian[1], hessian[2]), (0.0, 0.0, 0.0));
+    }
+
+    // a gaussian peak over a linear background, both normalized on the range
+    #[define_model(mixture(signal = f, background))]
+    mod fractions {
+        pub struct Parameters {
+            mu: Float,
+            c: Float,
+            f: Float,
+        }
+
+        pub struct Data {
+            #[range(0.0, 2.0)]
+            x: Float,
+        }
+
+        pub fn signal(p: Parameters, d: Data) -> Float {
+            (-(d.x - p.mu).powi(2)).exp() / (erf(2.0 - p.mu) + erf(p.mu)) / 0.886226925452758
+        }
+
+        pub fn background(p: Parameters, d: Data) -> Float {
+            0.5 + 0.5 * p.c * (d.x - 1.0)
+        }
+    }
+
+    // the same components weighted by yields, which only set the proportions
+    #[define_model(mixture(signal = s, background = b))]
+    mod yields {
+        pub struct Parameters {
+            mu: Float,
+            c: Float,
+            s: Float,
+            b: Float,
+        }
+
+        pub struct Data {
+            #[range(0.0, 2.0)]
+            x: Float,
+        }
+
+        pub fn signal(p: Parameters, d: Data) -> Float {
+            (-(d.x - p.mu).powi(2)).exp() / (erf(2.0 - p.mu) + erf(p.mu)) / 0.886226925452758
+        }
+
+        pub fn background(p: Parameters, d: Data) -> Float {
+            0.5 + 0.5 * p.c * (d.x - 1.0)
+        }
+    }
+
+    #[test]
+    fn mixtures_weight_their_components() {
+        let (mu, c) = (0.8, 0.3);
+        for x in [0.1, 0.9, 1.7] {
+            let data = [x];
+            let signal = fractions::signal(fractions::Parameters::from_array([mu, c, 0.0]), fractions::Data::from_array(data));
+            let background = fractions::background(fractions::Parameters::from_array([mu, c, 0.0]), fractions::Data::from_array(data));
+
+            let parameters = [mu, c, 0.35];
+            let density = 0.35 * signal + 0.65 * background;
+            assert_close(fractions::_dist(parameters, data), density, 1e-14, "fraction density");
+            assert_close(fractions::_likelihood(parameters, data), -density.ln(), 1e-14, "fraction likelihood");
+            assert_derivatives(|p| fractions::_likelihood(p, data), |p| fractions::_grad(p, data), |p| fractions::_hess(p, data), parameters);
+
+            let parameters = [mu, c, 700.0, 1300.0];
+            assert_eq!(yields::signal(yields::Parameters::from_array(parameters), yields::Data::from_array(data)), signal);
+            assert_eq!(yields::background(yields::Parameters::from_array(parameters), yields::Data::from_array(data)), background);
+            assert_close(yields::_dist(parameters, data), density, 1e-14, "yield density");
+            assert_close(yields::_likelihood(parameters, data), -density.ln(), 1e-14, "yield likelihood");
+            assert_derivatives(|p| yields::_likelihood(p, data), |p| yields::_grad(p, data), |p| yields::_hess(p, data), parameters);
+            // only the ratio of the yields matters
+            assert_close(yields::_likelihood([mu, c, 7.0, 13.0], data), -density.ln(), 1e-14, "scaled yields");
+        }
+
+        assert_eq!(fractions::Parameters::from_array([mu, c, 0.35]).to_array(), [mu, c, 0.35]);
+        assert_eq!(yields::Parameters::from_array([mu, c, 7.0, 13.0]).to_array(), [mu, c, 7.0, 13.0]);
+
+        // normalized components keep the mixture normalized
+        let integral = fitter::log_integral(|data| fractions::_dist([mu, c, 0.35], data), &fractions::DATA_DOMAIN, 40).exp();
+        assert_close(integral, 1.0, 1e-12, "fraction integral");
+    }
+
+    // independent densities of the mass and the decay time
+    #[define_model(product(mass, time))]
+    mod factorized {
+        pub struct Parameters {
+            mu: Float,
+            tau: Float,
+        }
+
+        pub struct Data {
+            #[range(4.0, 6.0)]
+            m: Float,
+            #[range(0.0, 5.0)]
+            t: Float,
+        }
+
+        pub fn mass(p: Parameters, d: Data) -> Float {
+            (-0.5 * (d.m - p.mu).powi(2) / 0.01).exp() / 0.25066282746310002
+        }
+
+        pub fn time(p: Parameters, d: Data) -> Float {
+            (-d.t / p.tau).exp() / p.tau
+        }
+    }
+
+    #[test]
+    fn products_sum_the_logs_of_their_factors() {
+        let parameters: [f64; 2] = [5.2, 1.4];
+        assert_eq!(factorized::Parameters::from_array(parameters).to_array(), parameters);
+        for data in [[5.15, 0.3], [5.3, 2.2], [4.9, 4.5]] {
+            let mass = factorized::mass(factorized::Parameters::from_array(parameters), factorized::Data::from_array(data));
+            let time = factorized::time(factorized::Parameters::from_array(parameters), factorized::Data::from_array(data));
+            assert_close(factorized::_dist(parameters, data), mass * time, 1e-14, "density");
+            assert_close(factorized::_likelihood(parameters, data), -mass.ln() - time.ln(), 1e-14, "likelihood");
+            assert_derivatives(|p| factorized::_likelihood(p, data), |p| factorized::_grad(p, data), |p| factorized::_hess(p, data), parameters);
+            // each parameter enters a single factor
+            assert_eq!(factorized::_hess(parameters, data).2[1], 0.0);
+        }
+    }
+
+    // a gaussian whose mean is split into two parameters, with the width fitted on a log scale
+    #[define_model]
+    mod split {
+        pub struct Parameters {
+            mu: Float,
+            sigma: Float,
+        }
+
+        pub struct Data {
+            #[range(-5.0, 5.0)]
+            x: Float,
+        }
+
+        pub fn distribution(p: Parameters, d: Data) -> Float {
+            (-0.5 * ((d.x - p.mu) / p.sigma).powi(2)).exp() / p.sigma
+        }
+
+        mod shifted {
+            pub struct Parameters {
+                base: Float,
+                shift: Float,
+                log_sigma: Float,
+            }
+
+            pub fn transformation(p: Parameters) -> super::Parameters {
+                super::Parameters { mu: p.base + p.shift, sigma: p.log_sigma.exp() }
+            }
+        }
+    }
+
+    #[test]
+    fn submodels_evaluate_the_base_model_at_their_transformation() {
+        let parameters: [f64; 3] = [0.4, -0.9, 0.2];
+        let transformed = split::shifted::transformation(split::shifted::Parameters::from_array(parameters)).to_array();
+        assert_eq!(transformed, [0.4 - 0.9, 0.2f64.exp()]);
+        assert_eq!(split::shifted::Parameters::from_array(parameters).to_array(), parameters);
+        for data in [[-1.2], [0.3], [2.6]] {
+            let density = split::distribution(split::Parameters::from_array(transformed), split::Data::from_array(data));
+            assert_close(split::_likelihood(transformed, data), -density.ln(), 1e-14, "base likelihood");
+            assert_close(split::shifted::_likelihood(parameters, data), split::_likelihood(transformed, data), 1e-14, "likelihood");
+            assert_close(split::shifted::_dist(parameters, data), split::_dist(transformed, data), 1e-14, "density");
+            assert_derivatives(|p| split::shifted::_likelihood(p, data), |p| split::shifted::_grad(p, data), |p| split::shifted::_hess(p, data), parameters);
+            // the two halves of the mean are interchangeable
+            let gradient = split::shifted::_grad(parameters, data).1;
+            assert_eq!(gradient[0], gradient[1]);
+        }
+    }
+
+    // an unnormalized gaussian, divided by its integral over the range
+    #[define_model(normalize)]
+    mod truncated {
+        pub struct Parameters {
+            mu: Float,
+            sigma: Float,
+        }
+
+        pub struct Data {
+            #[range(-1.0, 2.0)]
+            x: Float,
+        }
+
+        pub fn distribution(p: Parameters, d: Data) -> Float {
+            (-0.5 * ((d.x - p.mu) / p.sigma).powi(2)).exp()
+        }
+    }
+
+    #[test]
+    fn normalization_is_shared_by_every_path() {
+        let parameters: [f64; 2] = [0.4, 0.7];
+        let scale = parameters[1] * std::f64::consts::SQRT_2;
+        let integral = 0.5 * PI.sqrt() * scale * (special::erf((2.0 - parameters[0]) / scale) - special::erf((-1.0 - parameters[0]) / scale));
+        assert_close(truncated::_log_normalization(parameters), integral.ln(), 1e-12, "log normalization");
+        assert_eq!(truncated::_normalization(parameters).0, truncated::_log_normalization(parameters));
+
+        let events = [[-0.5], [0.3], [1.9]];
+        for data in events {
+            let density = truncated::distribution(truncated::Parameters::from_array(parameters), truncated::Data::from_array(data));
+            assert_close(truncated::_dist(parameters, data), density, 1e-14, "density");
+            assert_close(truncated::_likelihood(parameters, data), integral.ln() - density.ln(), 1e-12, "likelihood");
+            assert_derivatives(|p| truncated::_likelihood(p, data), |p| truncated::_grad(p, data), |p| truncated::_hess(p, data), parameters);
+        }
+
+        let column: Vec<f64> = events.iter().map(|event| event[0]).collect();
+        let total: f64 = events.iter().map(|data| truncated::_likelihood(parameters, *data)).sum();
+        assert_close(truncated::_likelihood_batch(parameters, [&column]), total, 1e-14, "batch value");
+        assert_close(truncated::_hess_batch(parameters, [&column]).0, total, 1e-14, "batch hessian value");
+    }
+
+    // the yield is the last parameter, and the density does not use it
+    #[define_model(extended(n))]
+    mod decay {
+        pub struct Parameters {
+            tau: Float,
+            n: Float,
+        }
+
+        pub struct Data {
+            #[range(0.0, 5.0)]
+            t: Float,
+        }
+
+        pub fn distribution(p: Parameters, d: Data) -> Float {
+            (-d.t / p.tau).exp() / p.tau
+        }
+
+        // the inverse of the exponential's distribution function, truncated to the range
+        pub fn generation(p: Parameters, random: &mut Random) -> Data {
+            let u = random.uniform() * (1.0 - (-5.0 / p.tau).exp());
+            Data { t: -p.tau * (1.0 - u).ln() }
+        }
+    }
+
+    #[test]
+    fn unused_parameters_keep_their_place() {
+        let parameters: [f64; 2] = [1.5, 200.0];
+        let data = [0.4];
+        let density = decay::distribution(decay::Parameters::from_array(parameters), decay::Data::from_array(data));
+        assert_close(decay::_dist(parameters, data), density, 1e-14, "density");
+        assert_close(decay::_likelihood(parameters, data), -density.ln() - 200.0f64.ln(), 1e-14, "likelihood");
+        assert_derivatives(|p| decay::_likelihood(p, data), |p| decay::_grad(p, data), |p| decay::_hess(p, data), parameters);
+        assert_eq!(decay::_extended(parameters), (200.0, [0.0, 1.0], [0.0; 3]));
+        assert_eq!(decay::Parameters::from_array(parameters).to_array(), parameters);
+
+        // `generation` is used over sampling the declared range
+        let events = decay::Model.generate(&parameters, 50, &mut Random::new(11));
+        let mut random = Random::new(11);
+        assert!(events.events().all(|event| event[0] == decay::generation(decay::Parameters::from_array(parameters), &mut random).to_array()[0]));
+        assert!(events.events().all(|event| (0.0..=5.0).contains(&event[0])));
+    }
+
+    #[test]
+    fn gaussian_functions_agree() {
+        let parameters: [f64; 2] = [0.3, 1.2];
+        let events = [[0.7], [-1.5], [2.25]];
+        for data in events {
+            let z = (data[0] - parameters[0]) / parameters[1];
+            let density = (-0.5 * z * z).exp() / (2.0 * PI * parameters[1]);
+            assert_close(gaussian::_dist(parameters, data), density, 1e-14, "density");
+            assert_close(gaussian::_likelihood(parameters, data), -density.ln(), 1e-14, "likelihood");
+            assert_derivatives(|p| gaussian::_likelihood(p, data), |p| gaussian::_grad(p, data), |p| gaussian::_hess(p, data), parameters);
+
+            assert_eq!(gaussian::_likelihood_event(&gaussian::_likelihood_prologue(parameters), data), gaussian::_likelihood(parameters, data));
+            assert_eq!(gaussian::_grad_event(&gaussian::_grad_prologue(parameters), data), gaussian::_grad(parameters, data));
+            assert_eq!(gaussian::_hess_event(&gaussian::_hess_prologue(parameters), data), gaussian::_hess(parameters, data));
+        }
+
+        let column: Vec<f64> = events.iter().map(|event| event[0]).collect();
+        let (value, gradient, hessian) = gaussian::_hess_batch(parameters, [&column]);
+        let (mut total, mut total_gradient, mut total_hessian) = (0.0, [0.0; 2], [0.0; 3]);
+        for data in events {
+            let (v, g, h) = gaussian::_hess(parameters, data);
+            total += v;
+            total_gradient.iter_mut().zip(g).for_each(|(t, g)| *t += g);
+            total_hessian.iter_mut().zip(h).for_each(|(t, h)| *t += h);
+        }
+        assert_close(value, total, 1e-14, "batch value");
+        (0..2).for_each(|i| assert_close(gradient[i], total_gradient[i], 1e-14, "batch gradient"));
+        (0..3).for_each(|i| assert_close(hessian[i], total_hessian[i], 1e-14, "batch hessian"));
+        assert_eq!(gaussian::_likelihood_batch(parameters, [&column]), gaussian::_grad_batch(parameters, [&column]).0);
+    }
+
+    #[test]
+    fn gaussian_layout_and_model() {
+        assert_eq!(gaussian::PARAMETER_NAMES, ["mu.mu", "sigma"]);
+        assert_eq!(gaussian::DATA_NAMES, ["x"]);
+        assert_eq!(gaussian::Parameters::from_array([0.3, 1.2]).to_array(), [0.3, 1.2]);
+        assert_eq!(gaussian::DATA_DOMAIN, [(-10.0, 10.0)]);
+
+        let model = gaussian::Model;
+        let (parameters, data) = ([0.3, 1.2], [0.7]);
+        assert_eq!(Model::value(&model, &parameters, &data), gaussian::_dist(parameters, data));
+        assert_eq!(Model::nll(&model, &parameters, &data), gaussian::_likelihood(parameters, data));
+        let (mut gradient, mut hessian) = ([0.0; 2], [0.0; 3]);
+        assert_eq!(model.nll_grad_hess(&parameters, &data, &mut gradient, &mut hessian), gaussian::_likelihood(parameters, data));
+        assert_eq!((gradient, hessian), (gaussian::_hess(parameters, data).1, gaussian::_hess(parameters, data).2));
+
+        let prepared = fitter::Likelihood::prepare(&model, &parameters, fitter::Order::Value);
+        assert_eq!(fitter::Likelihood::value(&model, &prepared, &data), gaussian::_likelihood(parameters, data));
+
+        let events = model.generate(&parameters, 1000, &mut Random::new(5));
+        assert_eq!(events.len(), 1000);
+        assert!(events.events().all(|event| (-10.0..=10.0).contains(&event[0])));
+    }
+
+    #[test]
+    fn b0s_phi_mu_mu_expands_its_loops() {
+        assert_eq!(b0s_phi_mu_mu::PARAMETER_NAMES.len(), 53);
+        assert_eq!(b0s_phi_mu_mu::PARAMETER_NAMES[..2], ["s.k.0", "s.k.1"]);
+        assert_eq!(b0s_phi_mu_mu::PARAMETER_NAMES[44..], ["s.m", "s.sigma_m", "s.x", "s.y", "s.gamma", "b.c0", "b.c1", "b.c2", "b.k_m"]);
+        assert_eq!(b0s_phi_mu_mu::DATA_NAMES, ["ctl", "ctk", "phi", "t", "m"]);
+
+        let parameters: [f64; 53] = std::array::from_fn(|i| match i {
+            0..11 => 0.6 + 0.05 * i as f64,
+            11..22 => 0.1 + 0.01 * i as f64,
+            22..44 => 0.03,
+            46 => 0.9,
+            47 => 0.08,
+            48 => 0.65,
+            _ => 1.0,
+        });
+        let data = [0.3, -0.4, 0.7, 1.1, 5.3];
+        let density = b0s_phi_mu_mu::distribution(b0s_phi_mu_mu::Parameters::from_array(parameters), b0s_phi_mu_mu::Data::from_array(data));
+        assert!(density > 0.0);
+        assert_eq!(b0s_phi_mu_mu::Parameters::from_array(parameters).to_array(), parameters);
+        assert_close(b0s_phi_mu_mu::_dist(parameters, data), density, 1e-12, "density");
+        assert_close(b0s_phi_mu_mu::_likelihood(parameters, data), -density.ln(), 1e-12, "likelihood");
+        assert_derivatives(|p| b0s_phi_mu_mu::_likelihood(p, data), |p| b0s_phi_mu_mu::_grad(p, data), |p| b0s_phi_mu_mu::_hess(p, data), parameters);
+
+        // the masses and the background do not enter the distribution
+        let gradient = b0s_phi_mu_mu::_grad(parameters, data).1;
+        [44, 45, 49, 50, 51, 52].into_iter().for_each(|i| assert_eq!(gradient[i], 0.0));
+        let events = b0s_phi_mu_mu::Model.generate(&parameters, 100, &mut Random::new(3));
+        assert_eq!(events.len(), 100);
+        assert!(events.events().all(|event| event[0].abs() <= 1.0 && event[1].abs() <= 1.0 && event[3] >= 0.0));
+    }
+}
@@ -1,5 +1,4 @@
 use crate::model::generation::distribution::Distribution;
-use crate::model::parameter::Parameter;
 
 pub struct Data {
     marginal: Distribution,
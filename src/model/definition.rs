@@ -0,0 +1,30 @@
+use fitter::Dataset;
+
+use crate::model::generation::random::Random;
+
+// implemented by `define_model` on the zero-sized `Model` of every generated module, with arrays laid out as in
+// `PARAMETER_NAMES` and `DATA_NAMES`
+pub trait Model {
+    type Parameters;
+    type Data;
+
+    const NUM_PARAMETERS: usize;
+    const NUM_DATA: usize;
+    const PARAMETER_NAMES: &'static [&'static str];
+    const DATA_NAMES: &'static [&'static str];
+
+    fn parameters_to_vec(parameters: &Self::Parameters) -> Vec<f64>;
+    fn parameters_from_slice(values: &[f64]) -> Self::Parameters;
+    fn data_to_vec(data: &Self::Data) -> Vec<f64>;
+    fn data_from_slice(values: &[f64]) -> Self::Data;
+
+    // the density, as written in the model
+    fn value(&self, parameters: &[f64], data: &[f64]) -> f64;
+
+    // per-event negative log-likelihood, with its gradient and packed upper-triangular hessian
+    fn nll(&self, parameters: &[f64], data: &[f64]) -> f64;
+    fn nll_grad(&self, parameters: &[f64], data: &[f64], gradient: &mut [f64]) -> f64;
+    fn nll_grad_hess(&self, parameters: &[f64], data: &[f64], gradient: &mut [f64], hessian: &mut [f64]) -> f64;
+
+    fn generate(&self, parameters: &[f64], count: usize, random: &mut Random) -> Dataset;
+}
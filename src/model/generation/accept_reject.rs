@@ -0,0 +1,95 @@
+use crate::model::generation::random::Random;
+
+const ENVELOPE_SAMPLES: usize = 10_000;
+const ENVELOPE_MARGIN: f64 = 1.2;
+
+// samples `count` events from an unnormalized `density` over the box `domain`
+pub fn accept_reject<const M: usize>(density: impl Fn([f64; M]) -> f64, domain: &[(f64, f64); M], count: usize, random: &mut Random) -> Vec<[f64; M]> {
+    let propose = |random: &mut Random| {
+        let mut event = [0.0; M];
+        for (value, (lower, upper)) in event.iter_mut().zip(domain) {
+            *value = random.uniform_range(*lower, *upper);
+        }
+        event
+    };
+
+    let mut maximum = (0..ENVELOPE_SAMPLES).map(|_| density(propose(random))).filter(|d| d.is_finite()).fold(0.0, f64::max) * ENVELOPE_MARGIN;
+    if maximum <= 0.0 {
+        panic!("unable to estimate an envelope, the density is not positive anywhere in the sampled domain");
+    }
+
+    let mut events = Vec::with_capacity(count);
+    while events.len() < count {
+        let event = propose(random);
+        let value = density(event);
+        if value > maximum {
+            // the envelope was underestimated, so everything accepted so far is biased
+            maximum = value * ENVELOPE_MARGIN;
+            events.clear();
+            continue;
+        }
+        if random.uniform() * maximum < value {
+            events.push(event);
+        }
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepted_events_follow_the_density() {
+        // `x (1 + y)` on `[0, 2] x [0, 1]`, whose `x` quarters hold 1, 3, 5 and 7 sixteenths and whose `y` mean is 5 / 9
+        let count = 100_000;
+        let events = accept_reject(|[x, y]| x * (1.0 + y), &[(0.0, 2.0), (0.0, 1.0)], count, &mut Random::new(41));
+        assert_eq!(events.len(), count);
+        assert!(events.iter().all(|[x, y]| (0.0..=2.0).contains(x) && (0.0..=1.0).contains(y)));
+
+        let mut histogram = [0usize; 4];
+        events.iter().for_each(|[x, _]| histogram[((x / 0.5) as usize).min(3)] += 1);
+        for (bin, sixteenths) in histogram.iter().zip([1.0, 3.0, 5.0, 7.0]) {
+            let expected = sixteenths / 16.0 * count as f64;
+            assert!((*bin as f64 - expected).abs() <= 5.0 * expected.sqrt(), "{} against {}", bin, expected);
+        }
+        let mean = events.iter().map(|[_, y]| y).sum::<f64>() / count as f64;
+        assert!((mean - 5.0 / 9.0).abs() <= 5.0 * (13.0f64 / 162.0 / count as f64).sqrt(), "{}", mean);
+    }
+
+    #[test]
+    fn envelope_grows_past_a_missed_peak() {
+        // a spike holding a sixth of the mass on a thousandth of the range, which the envelope estimate rarely sees
+        let density = |[x]: [f64; 1]| {
+            if (x - 0.5).abs() < 5e-4 {
+                200.0
+            } else {
+                1.0
+            }
+        };
+        let count = 20_000;
+        let events = accept_reject(density, &[(0.0, 1.0)], count, &mut Random::new(43));
+        let fraction = events.iter().filter(|[x]| (x - 0.5).abs() < 5e-4).count() as f64 / count as f64;
+        let expected = 0.2 / 1.199;
+        assert!((fraction - expected).abs() <= 5.0 * (expected * (1.0 - expected) / count as f64).sqrt(), "{} against {}", fraction, expected);
+    }
+
+    #[test]
+    fn undefined_points_are_left_out_of_the_envelope() {
+        let density = |[x]: [f64; 1]| {
+            if x < 0.5 {
+                f64::NAN
+            } else {
+                1.0
+            }
+        };
+        let events = accept_reject(density, &[(0.0, 1.0)], 1000, &mut Random::new(47));
+        assert!(events.iter().all(|[x]| *x >= 0.5));
+    }
+
+    #[test]
+    #[should_panic(expected = "unable to estimate an envelope")]
+    fn density_must_be_positive_somewhere() {
+        accept_reject(|[x]: [f64; 1]| -x, &[(0.0, 1.0)], 10, &mut Random::new(53));
+    }
+}
@@ -1,3 +1,5 @@
+use crate::model::generation::random::Random;
+
 pub enum Distribution {
     Discrete(Discrete),
     Uniform(Uniform),
@@ -7,7 +9,9 @@ pub enum Distribution {
 
 pub struct Discrete {
     outcomes: Vec<f64>,
-    probabilities: Vec<f64>,
+    // alias table, see Vose (1991)
+    thresholds: Vec<f64>,
+    aliases: Vec<usize>,
 }
 
 pub struct Uniform {
@@ -27,3 +31,285 @@ pub struct Gaussian {
     lower: f64,
     upper: f64,
 }
+
+impl Distribution {
+    pub fn sample(&self, random: &mut Random) -> f64 {
+        match self {
+            Self::Discrete(d) => d.sample(random),
+            Self::Uniform(u) => u.sample(random),
+            Self::Exponential(e) => e.sample(random),
+            Self::Gaussian(g) => g.sample(random),
+        }
+    }
+
+    pub fn sample_n(&self, count: usize, random: &mut Random) -> Vec<f64> {
+        (0..count).map(|_| self.sample(random)).collect()
+    }
+}
+
+impl Discrete {
+    pub fn new(outcomes: Vec<f64>, probabilities: Vec<f64>) -> Self {
+        if outcomes.len() != probabilities.len() || outcomes.is_empty() {
+            panic!("discrete distribution needs one probability per outcome and at least one outcome");
+        }
+        let total: f64 = probabilities.iter().sum();
+        if probabilities.iter().any(|p| !p.is_finite() || *p < 0.0) || total <= 0.0 {
+            panic!("discrete distribution probabilities must be finite, non-negative and not all zero");
+        }
+        let n = probabilities.len();
+        let mut thresholds: Vec<f64> = probabilities.iter().map(|p| p * n as f64 / total).collect();
+        let mut aliases: Vec<usize> = (0..n).collect();
+
+        let (mut small, mut large): (Vec<usize>, Vec<usize>) = (0..n).partition(|i| thresholds[*i] < 1.0);
+        while let (Some(s), Some(l)) = (small.pop(), large.pop()) {
+            aliases[s] = l;
+            thresholds[l] -= 1.0 - thresholds[s];
+            if thresholds[l] < 1.0 {
+                small.push(l);
+            } else {
+                large.push(l);
+            }
+        }
+        // whatever is left is only below one through rounding
+        small.into_iter().chain(large).for_each(|i| thresholds[i] = 1.0);
+
+        Self { outcomes, thresholds, aliases }
+    }
+
+    pub fn sample(&self, random: &mut Random) -> f64 {
+        let n = self.outcomes.len();
+        let i = ((random.uniform() * n as f64) as usize).min(n - 1);
+        if random.uniform() < self.thresholds[i] {
+            self.outcomes[i]
+        } else {
+            self.outcomes[self.aliases[i]]
+        }
+    }
+}
+
+impl Uniform {
+    pub fn new(lower: f64, upper: f64) -> Self {
+        if !lower.is_finite() || !upper.is_finite() || lower >= upper {
+            panic!("uniform distribution needs finite bounds with `lower < upper`");
+        }
+        Self { lower, upper }
+    }
+
+    pub fn sample(&self, random: &mut Random) -> f64 {
+        random.uniform_range(self.lower, self.upper)
+    }
+}
+
+impl Exponential {
+    // density proportional to `exp(-decay * x)` on `[lower, upper]`
+    pub fn new(decay: f64, lower: f64, upper: f64) -> Self {
+        if !lower.is_finite() || upper.is_nan() || lower >= upper || (decay <= 0.0 && !upper.is_finite()) {
+            panic!("exponential distribution needs `lower < upper`, and a finite upper bound unless `decay > 0`");
+        }
+        Self { decay, lower, upper }
+    }
+
+    pub fn sample(&self, random: &mut Random) -> f64 {
+        if self.decay == 0.0 {
+            return random.uniform_range(self.lower, self.upper);
+        }
+        // inverse cdf, written with `exp_m1`/`ln_1p` to stay accurate for small `decay * (upper - lower)`
+        let span = -(-self.decay * (self.upper - self.lower)).exp_m1();
+        let x = self.lower - (-random.uniform() * span).ln_1p() / self.decay;
+        x.clamp(self.lower, self.upper)
+    }
+}
+
+impl Gaussian {
+    pub fn new(mean: f64, std: f64, lower: f64, upper: f64) -> Self {
+        if std.is_nan() || std <= 0.0 || lower.is_nan() || upper.is_nan() || lower >= upper {
+            panic!("gaussian distribution needs `std > 0` and `lower < upper`");
+        }
+        Self { mean, std, lower, upper }
+    }
+
+    pub fn sample(&self, random: &mut Random) -> f64 {
+        let a = (self.lower - self.mean) / self.std;
+        let b = (self.upper - self.mean) / self.std;
+        let z = if b <= 0.0 {
+            -standard_truncated(-b, -a, random)
+        } else {
+            standard_truncated(a, b, random)
+        };
+        self.mean + self.std * z
+    }
+}
+
+// standard normal truncated to `[a, b]` with `b > 0`, following Robert (1995)
+fn standard_truncated(a: f64, b: f64, random: &mut Random) -> f64 {
+    if a <= 0.0 && b - a >= (2.0 * std::f64::consts::PI).sqrt() {
+        loop {
+            let z = random.normal();
+            if z >= a && z <= b {
+                return z;
+            }
+        }
+    }
+    if a > 0.0 {
+        let alpha = 0.5 * (a + (a * a + 4.0).sqrt());
+        let cutoff = a + 2.0 * (0.25 * (a * a - a * (a * a + 4.0).sqrt()) + 0.5).exp() / (a + (a * a + 4.0).sqrt());
+        if b > cutoff {
+            loop {
+                let z = a - (1.0 - random.uniform()).ln() / alpha;
+                if z <= b && random.uniform() <= (-0.5 * (z - alpha) * (z - alpha)).exp() {
+                    return z;
+                }
+            }
+        }
+    }
+    let rho = |z: f64| {
+        if a > 0.0 {
+            0.5 * (a * a - z * z)
+        } else {
+            -0.5 * z * z
+        }
+    };
+    loop {
+        let z = random.uniform_range(a, b);
+        if random.uniform() <= rho(z).exp() {
+            return z;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::special::erfc;
+    use std::f64::consts::{FRAC_1_SQRT_2, PI};
+
+    const SAMPLES: usize = 200_000;
+
+    fn moments(samples: &[f64]) -> (f64, f64) {
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        let variance = samples.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / (samples.len() - 1) as f64;
+        (mean, variance)
+    }
+
+    // mean and variance of the standard normal truncated to `[a, b]`
+    fn truncated_moments(a: f64, b: f64) -> (f64, f64) {
+        let density = |z: f64| (-0.5 * z * z).exp() / (2.0 * PI).sqrt();
+        let tail = |z: f64| 0.5 * erfc(z * FRAC_1_SQRT_2);
+        let mass = tail(a) - tail(b);
+        let edge = |z: f64| {
+            if z.is_finite() {
+                z * density(z)
+            } else {
+                0.0
+            }
+        };
+        let mean = (density(a) - density(b)) / mass;
+        (mean, 1.0 + (edge(a) - edge(b)) / mass - mean * mean)
+    }
+
+    #[test]
+    fn alias_table_reproduces_the_probabilities() {
+        let probabilities = [0.5, 0.0, 2.0, 1.0, 0.5];
+        let discrete = Discrete::new(vec![0.0, 1.0, 2.0, 3.0, 4.0], probabilities.to_vec());
+        let mut random = Random::new(17);
+        let mut counts = [0usize; 5];
+        (0..SAMPLES).for_each(|_| counts[discrete.sample(&mut random) as usize] += 1);
+        assert_eq!(counts[1], 0);
+        for (count, probability) in counts.iter().zip(probabilities.map(|p| p / 4.0)) {
+            let expected = probability * SAMPLES as f64;
+            assert!((*count as f64 - expected).abs() <= 5.0 * expected.sqrt().max(1.0), "{} against {}", count, expected);
+        }
+        // a single outcome is always drawn
+        assert_eq!(Discrete::new(vec![7.0], vec![3.0]).thresholds, [1.0]);
+        assert_eq!(Distribution::Discrete(Discrete::new(vec![7.0], vec![3.0])).sample_n(10, &mut random), [7.0; 10]);
+    }
+
+    #[test]
+    fn exponential_inverts_its_truncated_distribution_function() {
+        for (decay, lower, upper) in [(2.0, 0.5, 3.0), (-1.5, -1.0, 1.0), (1e-9, 0.0, 2.0), (0.7, 1.0, f64::INFINITY)] {
+            let samples = Distribution::Exponential(Exponential::new(decay, lower, upper)).sample_n(SAMPLES, &mut Random::new(23));
+            assert!(samples.iter().all(|x| (lower..=upper).contains(x)));
+            // the truncated mean, `lower + 1 / decay - width exp(-decay width) / (1 - exp(-decay width))`
+            let width = upper - lower;
+            let expected = if width.is_finite() {
+                lower + 1.0 / decay - width / (decay * width).exp_m1()
+            } else {
+                lower + 1.0 / decay
+            };
+            let (mean, variance) = moments(&samples);
+            assert!((mean - expected).abs() <= 5.0 * (variance / SAMPLES as f64).sqrt(), "decay {}: {} against {}", decay, mean, expected);
+        }
+        // without decay it is flat
+        let (mean, _) = moments(&Distribution::Exponential(Exponential::new(0.0, 2.0, 4.0)).sample_n(SAMPLES, &mut Random::new(29)));
+        assert!((mean - 3.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn truncated_gaussian_matches_its_moments_in_every_regime() {
+        let ranges = [
+            // wide around the mean, by rejecting normal draws
+            (-2.5, 3.0),
+            // far in the tail, by the exponential proposal
+            (2.0, f64::INFINITY),
+            (3.0, 8.0),
+            // narrow, by uniform proposals
+            (-0.4, 0.6),
+            (1.0, 1.3),
+            // entirely below the mean, mirrored
+            (f64::NEG_INFINITY, -2.5),
+        ];
+        for (a, b) in ranges {
+            let (mean, std) = (1.5, 0.4);
+            let gaussian = Gaussian::new(mean, std, mean + std * a, mean + std * b);
+            let mut random = Random::new(31);
+            let samples: Vec<f64> = (0..SAMPLES).map(|_| (gaussian.sample(&mut random) - mean) / std).collect();
+            assert!(samples.iter().all(|z| *z >= a && *z <= b), "[{}, {}]", a, b);
+            let (expected_mean, expected_variance) = if b <= 0.0 {
+                let (m, v) = truncated_moments(-b, -a);
+                (-m, v)
+            } else {
+                truncated_moments(a, b)
+            };
+            let (sample_mean, sample_variance) = moments(&samples);
+            let error = (expected_variance / SAMPLES as f64).sqrt();
+            assert!((sample_mean - expected_mean).abs() <= 5.0 * error, "mean on [{}, {}]: {} against {}", a, b, sample_mean, expected_mean);
+            assert!((sample_variance - expected_variance).abs() <= 0.02 * expected_variance, "variance on [{}, {}]: {} against {}", a, b, sample_variance, expected_variance);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "one probability per outcome")]
+    fn discrete_needs_matching_probabilities() {
+        Discrete::new(vec![1.0, 2.0], vec![1.0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "not all zero")]
+    fn discrete_needs_some_probability() {
+        Discrete::new(vec![1.0, 2.0], vec![0.0, 0.0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "finite bounds")]
+    fn uniform_needs_finite_bounds() {
+        Uniform::new(0.0, f64::INFINITY);
+    }
+
+    #[test]
+    #[should_panic(expected = "finite upper bound")]
+    fn exponential_needs_a_finite_range_without_decay() {
+        Exponential::new(-1.0, 0.0, f64::INFINITY);
+    }
+
+    #[test]
+    #[should_panic(expected = "`std > 0`")]
+    fn gaussian_needs_a_positive_width() {
+        Gaussian::new(0.0, 0.0, -1.0, 1.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "`lower < upper`")]
+    fn gaussian_needs_an_ordered_range() {
+        Gaussian::new(0.0, 1.0, 1.0, 1.0);
+    }
+}
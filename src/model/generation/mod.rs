@@ -1 +1,3 @@
+pub mod accept_reject;
 pub mod distribution;
+pub mod random;
@@ -1,7 +1,13 @@
 #[allow(dead_code)]
 mod data;
+mod definition;
 mod generation;
 mod parameter;
 
-pub use data::Data;
-pub use parameter::Parameter;
+pub use definition::Model;
+pub use generation::{
+    accept_reject::accept_reject,
+    distribution::{Discrete, Distribution, Exponential, Gaussian, Uniform},
+    random::Random,
+};
+pub use parameter::{Bounds, FloatingParameter, Parameter};
@@ -1,6 +1 @@
-use crate::model::data::Data;
-
-pub enum Parameter {
-    // Floating(FloatingParameter),
-    // Constrained(Graph),
-}
+pub use fitter::{Bounds, FloatingParameter, Parameter};
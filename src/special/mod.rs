@@ -0,0 +1,8 @@
+// special functions for generated model code, which calls them as `fastfit::special::*`
+mod erf;
+mod faddeeva;
+mod gamma;
+
+pub use erf::{erf, erfc};
+pub use faddeeva::{faddeeva, faddeeva_im, faddeeva_re};
+pub use gamma::{digamma, ln_gamma, trigamma};